@@ -0,0 +1,23 @@
+//! Print the node name and description of every sink, the default one marked.
+//!
+//! Run with `cargo run --example list_sinks`.
+
+use pwtrain::{Direction, PwError, init_roundtrip};
+
+fn main() -> Result<(), PwError> {
+    let result = init_roundtrip()?;
+    let default = result.default_sink_name();
+    for sink in result
+        .devices()
+        .iter()
+        .filter(|device| device.direction() == Direction::Input)
+    {
+        let marker = if Some(sink.node_name()) == default {
+            "*"
+        } else {
+            " "
+        };
+        println!("{marker} {} ({})", sink.node_name(), sink.description());
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use pwtrain::{InitOptions, InitResult, Session, init_roundtrip_with};
+use serde::Serialize;
+
+/// Timings of a run of snapshots, see [`benchmark`].
+#[derive(Debug, Serialize)]
+pub struct Report {
+    runs: usize,
+    session: bool,
+    sync_each_bind: bool,
+    min_ms: f64,
+    median_ms: f64,
+    max_ms: f64,
+    /// The counts of the last snapshot.
+    devices: usize,
+    globals: usize,
+    links: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let connection = if self.session {
+            "one session"
+        } else {
+            "a connection each"
+        };
+        let syncs = if self.sync_each_bind {
+            "a sync per bind"
+        } else {
+            "batched syncs"
+        };
+        writeln!(f, "runs     {} ({connection}, {syncs})", self.runs)?;
+        writeln!(f, "min      {:.2} ms", self.min_ms)?;
+        writeln!(f, "median   {:.2} ms", self.median_ms)?;
+        writeln!(f, "max      {:.2} ms", self.max_ms)?;
+        writeln!(f, "devices  {}", self.devices)?;
+        writeln!(f, "globals  {}", self.globals)?;
+        writeln!(f, "links    {}", self.links)
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Take `runs` snapshots with `opts` and time each, either over one [`Session`] or
+/// with a connection of its own each, as a plain snapshot does.
+pub fn benchmark(opts: &InitOptions, runs: usize, session: bool) -> Result<Report, String> {
+    if runs == 0 {
+        return Err("--runs must be at least 1".to_owned());
+    }
+    let reused = session
+        .then(Session::new)
+        .transpose()
+        .map_err(|err| err.to_string())?;
+    let mut times = Vec::with_capacity(runs);
+    let mut last = InitResult::default();
+    for _ in 0..runs {
+        let start = Instant::now();
+        last = match &reused {
+            Some(session) => session.snapshot(opts),
+            None => init_roundtrip_with(opts),
+        }
+        .map_err(|err| err.to_string())?;
+        times.push(start.elapsed());
+    }
+    times.sort_unstable();
+    Ok(Report {
+        runs,
+        session,
+        sync_each_bind: opts.sync_each_bind,
+        min_ms: millis(times[0]),
+        median_ms: millis(times[runs / 2]),
+        max_ms: millis(times[runs - 1]),
+        devices: last.devices().len(),
+        globals: last.decisions().len(),
+        links: last.links().len(),
+    })
+}
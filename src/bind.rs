@@ -0,0 +1,148 @@
+use std::{ffi::c_void, mem, pin::Pin, ptr, ptr::NonNull};
+
+use pipewire::{
+    self as pw,
+    node::NodeInfoRef,
+    registry::Registry,
+    spa::{self, param::ParamType, pod::Pod, spa_interface_call_method, sys as spa_sys},
+};
+
+/// Bind node `id` at interface `version`, which `Registry::bind` always takes from the
+/// libpipewire it was built with. `None` if the server refused at once.
+pub(crate) fn bind_node(
+    registry: &Registry,
+    id: u32,
+    version: u32,
+) -> Option<NonNull<pw::sys::pw_proxy>> {
+    // SAFETY: the registry is alive for the whole call and the interface type is a
+    // nul terminated string.
+    let proxy: *mut pw::sys::pw_proxy = unsafe {
+        spa_interface_call_method!(
+            registry.as_raw_ptr(),
+            pw::sys::pw_registry_methods,
+            bind,
+            id,
+            pw::sys::PW_TYPE_INTERFACE_Node.as_ptr().cast(),
+            version,
+            0
+        )
+    }
+    .cast();
+    NonNull::new(proxy)
+}
+
+type InfoCallback = Box<dyn Fn(&NodeInfoRef)>;
+type ParamCallback = Box<dyn Fn(i32, ParamType, u32, u32, Option<&Pod>)>;
+
+struct Callbacks {
+    info: InfoCallback,
+    param: ParamCallback,
+}
+
+/// A node bound at an interface version of our choosing, with the same info and
+/// param callbacks a `Node` listener takes. Dropping it removes the listener and
+/// destroys the proxy.
+pub(crate) struct VersionedNode {
+    proxy: NonNull<pw::sys::pw_proxy>,
+    // Need to stay allocated while the listener is registered.
+    _events: Pin<Box<pw::sys::pw_node_events>>,
+    listener: Pin<Box<spa_sys::spa_hook>>,
+    _callbacks: Box<Callbacks>,
+}
+
+impl VersionedNode {
+    pub(crate) fn bind<I, P>(
+        registry: &Registry,
+        id: u32,
+        version: u32,
+        info: I,
+        param: P,
+    ) -> Option<Self>
+    where
+        I: Fn(&NodeInfoRef) + 'static,
+        P: Fn(i32, ParamType, u32, u32, Option<&Pod>) + 'static,
+    {
+        unsafe extern "C" fn on_info(data: *mut c_void, info: *const pw::sys::pw_node_info) {
+            // SAFETY: `data` is the `Callbacks` registered below, alive as long as the
+            // listener, and `NodeInfoRef` is a transparent wrapper of `pw_node_info`.
+            unsafe {
+                let callbacks = &*data.cast::<Callbacks>();
+                if let Some(info) = info.cast::<NodeInfoRef>().as_ref() {
+                    (callbacks.info)(info);
+                }
+            }
+        }
+        unsafe extern "C" fn on_param(
+            data: *mut c_void,
+            seq: i32,
+            id: u32,
+            index: u32,
+            next: u32,
+            param: *const spa_sys::spa_pod,
+        ) {
+            // SAFETY: as in `on_info`, and a non-null `param` is a pod the server sent,
+            // valid for the duration of the call.
+            unsafe {
+                let callbacks = &*data.cast::<Callbacks>();
+                let param = (!param.is_null()).then(|| Pod::from_raw(param));
+                (callbacks.param)(seq, ParamType::from_raw(id), index, next, param);
+            }
+        }
+
+        let proxy = bind_node(registry, id, version)?;
+        let callbacks = Box::new(Callbacks {
+            info: Box::new(info),
+            param: Box::new(param),
+        });
+        // SAFETY: the events, hook and callbacks are boxed and kept in the returned
+        // value, so they stay put for as long as the listener is registered.
+        let (events, listener) = unsafe {
+            let mut events: Pin<Box<pw::sys::pw_node_events>> = Box::pin(mem::zeroed());
+            events.version = pw::sys::PW_VERSION_NODE_EVENTS;
+            events.info = Some(on_info);
+            events.param = Some(on_param);
+            let mut listener: Pin<Box<spa_sys::spa_hook>> = Box::pin(mem::zeroed());
+            let listener_ptr: *mut spa_sys::spa_hook = listener.as_mut().get_unchecked_mut();
+            spa_interface_call_method!(
+                proxy.as_ptr(),
+                pw::sys::pw_node_methods,
+                add_listener,
+                listener_ptr,
+                events.as_ref().get_ref(),
+                ptr::from_ref(callbacks.as_ref()).cast_mut().cast()
+            );
+            (events, listener)
+        };
+        Some(Self {
+            proxy,
+            _events: events,
+            listener,
+            _callbacks: callbacks,
+        })
+    }
+
+    /// Ask for every param of type `id`, answered through the param callback.
+    pub(crate) fn enum_params(&self, id: ParamType) {
+        // SAFETY: the proxy is a node proxy and alive until `self` is dropped.
+        unsafe {
+            spa_interface_call_method!(
+                self.proxy.as_ptr(),
+                pw::sys::pw_node_methods,
+                enum_params,
+                0,
+                id.as_raw(),
+                0,
+                u32::MAX,
+                ptr::null()
+            );
+        }
+    }
+}
+
+impl Drop for VersionedNode {
+    fn drop(&mut self) {
+        spa::utils::hook::remove(*self.listener);
+        // SAFETY: the proxy was bound in `bind` and is destroyed only here.
+        unsafe { pw::sys::pw_proxy_destroy(self.proxy.as_ptr()) };
+    }
+}
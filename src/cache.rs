@@ -0,0 +1,73 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pwtrain::InitResult;
+use serde::{Deserialize, Serialize};
+
+/// The last snapshot, stored between runs by `--cache-ttl`.
+///
+/// Only what goes into the JSON output survives the trip through the file, plus the
+/// raw props, which filters and config fields read. A cached result has no metadata
+/// checks or decisions.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// The options the snapshot was taken with; a cache for other options is a miss.
+    key: String,
+    taken_ms: u128,
+    result: InitResult,
+    /// The raw props of every device, by id, which the JSON of `result` leaves out.
+    #[serde(default)]
+    props: BTreeMap<u32, BTreeMap<String, String>>,
+}
+
+/// `$XDG_RUNTIME_DIR/pwtrain-snapshot.json`. The runtime dir belongs to the user
+/// alone, unlike a shared temp dir where another user could plant the file, so
+/// without one there is no cache.
+fn path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty())?;
+    Some(PathBuf::from(dir).join("pwtrain-snapshot.json"))
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or_default()
+}
+
+/// The cached snapshot for `key`, if it is younger than `ttl`.
+pub fn load(key: &str, ttl: Duration) -> Option<InitResult> {
+    let json = fs::read_to_string(path()?).ok()?;
+    let entry: Entry = serde_json::from_str(&json).ok()?;
+    let age = now_ms().checked_sub(entry.taken_ms)?;
+    if entry.key != key || age > ttl.as_millis() {
+        return None;
+    }
+    let mut result = entry.result;
+    result.restore_props(entry.props);
+    Some(result)
+}
+
+/// Replace the cached snapshot. Failing to write the cache is not worth an error.
+pub fn store(key: &str, result: &InitResult) {
+    let Some(path) = path() else {
+        return;
+    };
+    let entry = Entry {
+        key: key.to_owned(),
+        taken_ms: now_ms(),
+        result: result.clone(),
+        props: result
+            .devices()
+            .iter()
+            .map(|device| (device.id(), device.props().clone()))
+            .collect(),
+    };
+    if let Ok(json) = serde_json::to_string(&entry) {
+        let _ = fs::write(path, json);
+    }
+}
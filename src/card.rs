@@ -0,0 +1,291 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    io::Cursor,
+    rc::Rc,
+};
+
+use pipewire::{
+    self as pw,
+    device::{Device as DeviceProxy, DeviceListener},
+    spa::{
+        param::ParamType,
+        pod::{Object, Pod, Property, Value, serialize::PodSerializer},
+        sys,
+        utils::{Id, SpaTypes},
+    },
+    types::ObjectType,
+};
+use serde::Serialize;
+
+use crate::{PwError, params::pod_object, roundtrip::connect, state::Pending};
+
+/// Whether a card profile can be used right now, e.g. whether something is
+/// plugged into the jack it needs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Availability {
+    #[default]
+    Unknown,
+    No,
+    Yes,
+}
+
+impl Availability {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            sys::SPA_PARAM_AVAILABILITY_no => Self::No,
+            sys::SPA_PARAM_AVAILABILITY_yes => Self::Yes,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// One entry of a card's `EnumProfile` params.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CardProfile {
+    index: i32,
+    name: String,
+    description: String,
+    priority: i32,
+    available: Availability,
+}
+
+impl CardProfile {
+    pub fn index(&self) -> i32 {
+        self.index
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+    pub fn available(&self) -> Availability {
+        self.available
+    }
+
+    fn from_object(object: &Object) -> Self {
+        let mut profile = Self::default();
+        for property in &object.properties {
+            match (property.key, &property.value) {
+                (sys::SPA_PARAM_PROFILE_index, Value::Int(index)) => profile.index = *index,
+                (sys::SPA_PARAM_PROFILE_name, Value::String(name)) => {
+                    profile.name = name.clone();
+                }
+                (sys::SPA_PARAM_PROFILE_description, Value::String(description)) => {
+                    profile.description = description.clone();
+                }
+                (sys::SPA_PARAM_PROFILE_priority, Value::Int(priority)) => {
+                    profile.priority = *priority;
+                }
+                (sys::SPA_PARAM_PROFILE_available, Value::Id(Id(available))) => {
+                    profile.available = Availability::from_raw(*available);
+                }
+                _ => {}
+            }
+        }
+        profile
+    }
+}
+
+/// A `Device` global, i.e. a sound card, with every profile it offers.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Card {
+    id: u32,
+    name: String,
+    description: String,
+    profiles: Vec<CardProfile>,
+    /// The index of the profile the card is on.
+    active: Option<i32>,
+}
+
+impl Card {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+    /// All profiles, including the ones the card reports as unavailable.
+    pub fn profiles(&self) -> &[CardProfile] {
+        &self.profiles
+    }
+    /// The profile the card is on, from its `Profile` param.
+    pub fn active_profile(&self) -> Option<&CardProfile> {
+        self.profiles
+            .iter()
+            .find(|profile| Some(profile.index) == self.active)
+    }
+}
+
+/// List the cards with their profiles and the one they are on, ordered by id.
+pub fn list_cards() -> Result<Vec<Card>, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let cards: Rc<RefCell<BTreeMap<u32, Card>>> = Rc::new(RefCell::new(BTreeMap::new()));
+    let requests: Rc<RefCell<Vec<(DeviceProxy, DeviceListener)>>> = Rc::new(RefCell::new(vec![]));
+    let peddings = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+    peddings.push(pending);
+
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let peddings = peddings.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                if peddings.complete(seq) == Some(0) {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let registry = registry.clone();
+            let cards = cards.clone();
+            let core = core.clone();
+            move |global| {
+                if global.type_ != ObjectType::Device {
+                    return;
+                }
+                let Ok(device) = registry.bind::<DeviceProxy, _>(global) else {
+                    return;
+                };
+                let get = |key: &str| {
+                    global
+                        .props
+                        .and_then(|props| props.get(key))
+                        .unwrap_or_default()
+                        .to_owned()
+                };
+                let id = global.id;
+                cards.borrow_mut().insert(
+                    id,
+                    Card {
+                        id,
+                        name: get("device.name"),
+                        description: get("device.description"),
+                        profiles: vec![],
+                        active: None,
+                    },
+                );
+                let listener = device
+                    .add_listener_local()
+                    .param({
+                        let cards = cards.clone();
+                        move |_, param_type, _, _, pod| {
+                            let Some(object) = pod.and_then(pod_object) else {
+                                return;
+                            };
+                            let mut cards = cards.borrow_mut();
+                            let Some(card) = cards.get_mut(&id) else {
+                                return;
+                            };
+                            match param_type {
+                                ParamType::EnumProfile => {
+                                    card.profiles.push(CardProfile::from_object(&object));
+                                }
+                                ParamType::Profile => {
+                                    card.active = Some(CardProfile::from_object(&object).index);
+                                }
+                                _ => {}
+                            }
+                        }
+                    })
+                    .register();
+                device.enum_params(0, Some(ParamType::EnumProfile), 0, u32::MAX);
+                device.enum_params(0, Some(ParamType::Profile), 0, u32::MAX);
+                let pending = core.sync(0).expect("sync failed");
+                peddings.push(pending);
+                requests.borrow_mut().push((device, listener));
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let cards = cards.take().into_values().collect();
+    Ok(cards)
+}
+
+/// Switch card `card` to the profile with `index`, as `pactl set-card-profile` does.
+///
+/// Returns whether the card exists. The server applies the profile on its own
+/// time, so its nodes may still be the old ones right after this returns.
+pub fn set_card_profile(card: u32, index: i32) -> Result<bool, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let object = Object {
+        type_: SpaTypes::ObjectParamProfile.as_raw(),
+        id: ParamType::Profile.as_raw(),
+        properties: vec![
+            Property::new(sys::SPA_PARAM_PROFILE_index, Value::Int(index)),
+            Property::new(sys::SPA_PARAM_PROFILE_save, Value::Bool(true)),
+        ],
+    };
+    let bytes = PodSerializer::serialize(Cursor::new(Vec::new()), &Value::Object(object))
+        .expect("serializing a profile pod")
+        .0
+        .into_inner();
+    let found = Rc::new(Cell::new(false));
+    let bound: Rc<RefCell<Option<DeviceProxy>>> = Rc::new(RefCell::new(None));
+    let peddings = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+    peddings.push(pending);
+
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let peddings = peddings.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                if peddings.complete(seq) == Some(0) {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let registry = registry.clone();
+            let found = found.clone();
+            let core = core.clone();
+            move |global| {
+                if global.type_ != ObjectType::Device || global.id != card {
+                    return;
+                }
+                let Ok(device) = registry.bind::<DeviceProxy, _>(global) else {
+                    return;
+                };
+                let Some(pod) = Pod::from_bytes(&bytes) else {
+                    return;
+                };
+                device.set_param(ParamType::Profile, 0, pod);
+                found.set(true);
+                bound.replace(Some(device));
+                let pending = core.sync(0).expect("sync failed");
+                peddings.push(pending);
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    Ok(found.get())
+}
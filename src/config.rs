@@ -0,0 +1,89 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Settings read from the config file, by default `$XDG_CONFIG_HOME/pwtrain/config`.
+///
+/// The file is made of `[section]` headings followed by `key = value` lines. Blank
+/// lines and lines starting with `#` are skipped, as are sections nothing reads.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// From the `[fields]` section: node props to show as extra columns, as
+    /// `(prop key, column name)` in the order they are listed.
+    pub fields: Vec<(String, String)>,
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("pwtrain").join("config"))
+}
+
+impl Config {
+    /// Read `path`, or the default file when it is `None`. A missing default file is
+    /// an empty config, while a missing `path` is an error.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|err| format!("{}: {err}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(err) => Err(format!("failed to read {}: {err}", path.display())),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut section = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = Some(name.trim());
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected `key = value`", number + 1));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key.is_empty() || value.is_empty() {
+                return Err(format!("line {}: expected `key = value`", number + 1));
+            }
+            if section == Some("fields") {
+                config.fields.push((key.to_owned(), value.to_owned()));
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_fields_section_maps_props() {
+        let config = Config::parse(
+            "# columns\n[fields]\napi.alsa.path = alsa_path\n\n[other]\nnode.name = ignored\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.fields,
+            [("api.alsa.path".to_owned(), "alsa_path".to_owned())]
+        );
+        assert!(Config::parse("[fields]\napi.alsa.path\n").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use pwtrain::{Device, Profile, PwEvent, Settings, apply_profile};
+use serde_json::{Value, json};
+
+/// What a watch knows about the server, kept up to date from its events so that
+/// the control socket can answer without a roundtrip of its own.
+#[derive(Debug, Default)]
+pub struct ControlState {
+    devices: BTreeMap<u32, Device>,
+    settings: Settings,
+}
+
+impl ControlState {
+    pub fn record(&mut self, event: &PwEvent) {
+        match event {
+            PwEvent::DeviceAdded(device) | PwEvent::DeviceChanged(device, _) => {
+                self.devices.insert(device.id(), device.clone());
+            }
+            PwEvent::DeviceRemoved(id) => {
+                self.devices.remove(id);
+            }
+            PwEvent::SettingsChanged(settings) => self.settings = settings.clone(),
+            PwEvent::Snapshot(devices, settings) => {
+                self.devices = devices
+                    .iter()
+                    .map(|device| (device.id(), device.clone()))
+                    .collect();
+                self.settings = settings.clone();
+            }
+            PwEvent::Synced | PwEvent::Reconnected(_) => {}
+        }
+    }
+}
+
+/// A listening control socket. The socket file is removed when this is dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Listen on `path` and answer every client on a thread of its own, so that one that
+/// stays connected does not hold up the others. Only the owner may connect.
+///
+/// Every request is a line, answered with one line of JSON:
+/// - `list`: the devices.
+/// - `get rate`, `get quantum`: what the graph runs at, the forced value if set.
+/// - `set rate N`, `set quantum N`: force the value, 0 to stop forcing, as
+///   `load-profile` would. There is nobody to ask on a socket, so this is refused
+///   unless `assume_yes` is set, i.e. the watch was started with `--assume-yes`.
+///
+/// A socket file nobody listens on any more is replaced; a live one, or a path that
+/// is not a socket, is an error.
+pub fn serve(
+    path: &Path,
+    state: Arc<Mutex<ControlState>>,
+    assume_yes: bool,
+) -> io::Result<ControlSocket> {
+    let listener = match UnixListener::bind(path) {
+        Err(err)
+            if err.kind() == io::ErrorKind::AddrInUse
+                && fs::symlink_metadata(path)?.file_type().is_socket()
+                && UnixStream::connect(path).is_err() =>
+        {
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        }
+        bound => bound?,
+    };
+    // `set` changes the graph, so the socket is not left to the umask.
+    if let Err(err) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        let _ = fs::remove_file(path);
+        return Err(err);
+    }
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let state = state.clone();
+            thread::spawn(move || handle(stream, &state, assume_yes));
+        }
+    });
+    Ok(ControlSocket {
+        path: path.to_owned(),
+    })
+}
+
+fn handle(stream: UnixStream, state: &Mutex<ControlState>, assume_yes: bool) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match respond(&line, state, assume_yes) {
+            Ok(response) => response,
+            Err(err) => json!({ "ok": false, "error": err }),
+        };
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+fn respond(line: &str, state: &Mutex<ControlState>, assume_yes: bool) -> Result<Value, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let state = || state.lock().unwrap_or_else(|err| err.into_inner());
+    match words[..] {
+        ["list"] => {
+            let devices: Vec<Device> = state().devices.values().cloned().collect();
+            Ok(json!({ "ok": true, "devices": devices }))
+        }
+        ["get", "rate"] => Ok(json!({ "ok": true, "rate": state().settings.effective_rate() })),
+        ["get", "quantum"] => Ok(json!({
+            "ok": true,
+            "quantum": state().settings.effective_quantum(),
+        })),
+        ["set", "rate" | "quantum", _] if !assume_yes => Err("set needs --assume-yes".to_owned()),
+        ["set", key @ ("rate" | "quantum"), value] => {
+            let value: u32 = value
+                .parse()
+                .map_err(|err| format!("invalid {key} {value}: {err}"))?;
+            let profile = match key {
+                "rate" => Profile {
+                    force_rate: Some(value),
+                    ..Default::default()
+                },
+                _ => Profile {
+                    force_quantum: Some(value),
+                    ..Default::default()
+                },
+            };
+            let mut warnings = vec![];
+            let applied = apply_profile(&profile, false, |warning| warnings.push(warning))
+                .map_err(|err| err.to_string())?;
+            Ok(json!({ "ok": applied > 0, "warnings": warnings }))
+        }
+        _ => Err(format!("unknown command: {line}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_is_refused_without_assume_yes() {
+        let state = Mutex::new(ControlState::default());
+        assert_eq!(
+            respond("set rate 48000", &state, false),
+            Err("set needs --assume-yes".to_owned())
+        );
+        let rate = respond("get rate", &state, false).unwrap();
+        assert_eq!(rate["ok"], true);
+    }
+
+    #[test]
+    fn an_idle_client_does_not_block_the_others() {
+        let path = std::env::temp_dir().join(format!("pwtrain-control-{}", std::process::id()));
+        let _socket = serve(&path, Arc::default(), false).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let _idle = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, "get quantum").unwrap();
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["ok"], true);
+    }
+}
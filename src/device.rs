@@ -0,0 +1,803 @@
+use std::{collections::BTreeMap, fmt};
+
+use pipewire::spa::utils::dict::DictRef;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Settings,
+    filter::glob_match,
+    link::Graph,
+    params::{Volume, channel_positions, prop_list},
+};
+
+/// Which way audio flows through a device, seen from the device.
+///
+/// Defaults to [`Input`](Direction::Input), a sink, so that a `Device::default()`
+/// fixture looks like the most common kind of node.
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// A sink: audio goes into the device, e.g. speakers.
+    #[default]
+    #[serde(rename = "sink")]
+    Input,
+    /// A source: audio comes out of the device, e.g. a microphone.
+    #[serde(rename = "source")]
+    Output,
+}
+
+impl Direction {
+    /// The label used in every output format: `sink` or `source`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Input => "sink",
+            Direction::Output => "source",
+        }
+    }
+
+    pub(crate) fn from_media_class(media_class: &str) -> Option<Self> {
+        match media_class {
+            "Audio/Sink" => Some(Direction::Input),
+            "Audio/Source" => Some(Direction::Output),
+            _ => None,
+        }
+    }
+
+    /// The direction of any `media.class`, judged by its `Sink` or `Source` suffix.
+    fn from_any_media_class(media_class: &str) -> Self {
+        if media_class.ends_with("Source") {
+            Direction::Output
+        } else {
+            Direction::Input
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Props [`Device::from_props`] falls back to a default for.
+const EXPECTED_PROPS: [&str; 4] = [
+    "node.name",
+    "node.nick",
+    "node.description",
+    "audio.channels",
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    id: u32,
+    serial: Option<u64>,
+    node_name: String,
+    nick_name: String,
+    description: String,
+    media_class: String,
+    direction: Direction,
+    channels: usize,
+    channel_positions: Vec<String>,
+    channel_positions_raw: Vec<u32>,
+    limit_quantum: u32,
+    requested_quantum: Option<u32>,
+    rate: u32,
+    allow_rates: Vec<u32>,
+    quantum: u32,
+    min_quantum: u32,
+    max_quantum: u32,
+    alsa_card: Option<i32>,
+    alsa_device: Option<i32>,
+    group: Option<String>,
+    card: Option<u32>,
+    card_name: Option<String>,
+    api: Option<String>,
+    factory_name: Option<String>,
+    monitor_source_id: Option<u32>,
+    media_name: Option<String>,
+    media_title: Option<String>,
+    media_category: Option<String>,
+    preferred_rate: Option<u32>,
+    rates: Vec<u32>,
+    volumes: Vec<f32>,
+    mute: Option<bool>,
+    sample_format: Option<String>,
+    #[serde(default)]
+    node_virtual: bool,
+    #[serde(default)]
+    members: Vec<u32>,
+    #[serde(default)]
+    in_use: bool,
+    permissions: Option<String>,
+    #[serde(default)]
+    allowed_rates: Vec<u32>,
+    #[serde(default)]
+    running_rate: Option<u32>,
+    #[serde(skip)]
+    missing_props: Vec<&'static str>,
+    #[serde(skip)]
+    props: BTreeMap<String, String>,
+}
+
+impl Device {
+    /// Start a device by hand, for tests and for callers that feed their own data to
+    /// code written against [`Device`]. Everything not set stays at its default.
+    pub fn builder(id: u32) -> DeviceBuilder {
+        DeviceBuilder(Device {
+            id,
+            ..Default::default()
+        })
+    }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// The `object.serial`, which unlike the id is never reused, so a device that
+    /// reconnects can be told apart from the one before it.
+    pub fn serial(&self) -> Option<u64> {
+        self.serial
+    }
+    pub fn name(&self) -> &str {
+        &self.nick_name
+    }
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+    /// The channel names from `audio.position`, such as `FL` and `FR`.
+    pub fn channel_positions(&self) -> &[String] {
+        &self.channel_positions
+    }
+    /// The `SPA_AUDIO_CHANNEL_*` ids of [`channel_positions`](Self::channel_positions),
+    /// in the same order.
+    pub fn channel_positions_raw(&self) -> &[u32] {
+        &self.channel_positions_raw
+    }
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+    /// [`direction`](Self::direction) as `sink` or `source`.
+    pub fn direction_str(&self) -> &'static str {
+        self.direction.as_str()
+    }
+    /// The raw `media.class`, such as `Audio/Sink` or `Video/Source`.
+    pub fn media_class(&self) -> &str {
+        &self.media_class
+    }
+    pub fn node_name(&self) -> &str {
+        &self.node_name
+    }
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+    pub fn limit_quantam(&self) -> u32 {
+        self.limit_quantum
+    }
+    /// The buffer size the node asks for, where [`limit_quantam`](Self::limit_quantam)
+    /// is the ceiling: the quantum of its `node.latency`, or for ALSA devices the
+    /// `api.alsa.period-size`.
+    ///
+    /// This is a request, not what the graph runs: the driver picks one quantum for
+    /// every node it drives, which clients cannot read from the node itself.
+    pub fn requested_quantum(&self) -> Option<u32> {
+        self.requested_quantum
+    }
+    pub fn min_quantum(&self) -> u32 {
+        self.min_quantum
+    }
+    pub fn max_quantum(&self) -> u32 {
+        self.max_quantum
+    }
+    pub fn quantum(&self) -> u32 {
+        self.quantum
+    }
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+    pub fn allow_rates(&self) -> &[u32] {
+        &self.allow_rates
+    }
+    /// The ALSA card index, matching the `card N` column of `aplay -l`.
+    pub fn alsa_card(&self) -> Option<i32> {
+        self.alsa_card
+    }
+    /// The ALSA device number on [`alsa_card`](Self::alsa_card).
+    pub fn alsa_device(&self) -> Option<i32> {
+        self.alsa_device
+    }
+    /// The `node.group` the node is scheduled with, if any.
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+    /// The id of the `Device` global (the card) the node belongs to, from `device.id`.
+    pub fn card(&self) -> Option<u32> {
+        self.card
+    }
+    /// A readable name for [`card`](Self::card), from `alsa.card_name` or `device.description`.
+    pub fn card_name(&self) -> Option<&str> {
+        self.card_name.as_deref()
+    }
+    /// The backend that drives the node, such as `alsa` or `bluez5`, from `device.api`.
+    pub fn api(&self) -> Option<&str> {
+        self.api.as_deref()
+    }
+    /// The `factory.name` the node was created with, such as `support.null-audio-sink`
+    /// for the null and combine sinks loaded by modules.
+    pub fn factory_name(&self) -> Option<&str> {
+        self.factory_name.as_deref()
+    }
+    /// For a sink, the id of the source named after it with a `.monitor` suffix.
+    ///
+    /// PipeWire usually lets clients record a sink through the sink's own monitor
+    /// ports, so this is only set when a separate monitor node exists.
+    pub fn monitor_source_id(&self) -> Option<u32> {
+        self.monitor_source_id
+    }
+    /// Whether the device is backed by real hardware rather than being a virtual
+    /// sink or an application stream.
+    ///
+    /// Hardware nodes belong to a device created by a backend such as ALSA or BlueZ,
+    /// so they carry a `device.api`. Nodes that have none, `Stream/*` nodes, and null
+    /// sinks (`factory.name` `support.null-audio-sink`) are all taken as virtual.
+    pub fn is_hardware(&self) -> bool {
+        self.api.is_some()
+            && !self.media_class.starts_with("Stream/")
+            && self.factory_name.as_deref() != Some("support.null-audio-sink")
+    }
+    /// The `media.name` of a stream, usually what the application calls it, such as
+    /// `Playback` or the name of a browser tab. `None` for nodes that are not streams.
+    pub fn media_name(&self) -> Option<&str> {
+        self.media_name.as_deref()
+    }
+    /// The `media.title` of a stream, such as the track being played. `None` for
+    /// nodes that are not streams.
+    pub fn media_title(&self) -> Option<&str> {
+        self.media_title.as_deref()
+    }
+    /// The `media.category`: `Playback`, `Capture`, `Duplex` or `Monitor`. Unlike the
+    /// direction, it tells a duplex node apart.
+    pub fn media_category(&self) -> Option<&str> {
+        self.media_category.as_deref()
+    }
+    /// Whether the node has `node.virtual` set, as modules do for the sinks and
+    /// sources they create. Not the same as `!is_hardware()`, since streams are not
+    /// marked virtual.
+    pub fn is_virtual(&self) -> bool {
+        self.node_virtual
+    }
+    /// For a combine or loopback node, the ids of the nodes it is made of: the sinks
+    /// a combine sink plays to, or where a loopback sends or takes its audio.
+    ///
+    /// Found through the links of the other nodes in its `node.link-group`, so it is
+    /// empty for ordinary nodes and for members that are not linked yet.
+    pub fn members(&self) -> &[u32] {
+        &self.members
+    }
+    /// Whether an application stream is linked to the node right now, playing into a
+    /// sink or recording from a source, directly or through nodes in between such as
+    /// a combine sink. `false` when no such links are found.
+    pub fn in_use(&self) -> bool {
+        self.in_use
+    }
+    /// What this client may do with the node, as announced by the registry and shown
+    /// like `pw-cli` does: `r` read, `w` write such as setting params, `x` call methods,
+    /// `m` set metadata on it, with `-` for each one missing. A sandboxed client, e.g.
+    /// under Flatpak, often gets `r---`, which is why setting things fails there.
+    pub fn permissions(&self) -> Option<&str> {
+        self.permissions.as_deref()
+    }
+    /// The rate the node asks the graph for through `node.rate`, which can differ
+    /// from the global clock rate.
+    pub fn preferred_rate(&self) -> Option<u32> {
+        self.preferred_rate
+    }
+    /// The rates the node may be switched to, from the `audio.allowed-rates` list in
+    /// its props, sorted. Unlike [`rates`](Self::rates), which is what the hardware
+    /// can do, this is what the node was configured to allow, and unlike
+    /// [`allow_rates`](Self::allow_rates) it is set per node rather than for the graph.
+    pub fn allowed_rates(&self) -> &[u32] {
+        &self.allowed_rates
+    }
+    /// The rate of the `Format` param the node negotiated, i.e. the rate it actually
+    /// runs at. `None` while it is suspended, since it has no format then.
+    pub fn running_rate(&self) -> Option<u32> {
+        self.running_rate
+    }
+    /// The sample rates the node advertises in its `EnumFormat` params.
+    pub fn rates(&self) -> &[u32] {
+        &self.rates
+    }
+    /// The volume of each channel as a linear factor, 1.0 being unity gain.
+    pub fn volumes(&self) -> &[f32] {
+        &self.volumes
+    }
+    /// Each channel volume paired with its position, such as `("FL", 0.8)`. Channels
+    /// are named by index instead when the positions are unknown or do not line up.
+    pub fn channel_volumes(&self) -> Vec<(String, f32)> {
+        let named = self.channel_positions.len() == self.volumes.len();
+        self.volumes
+            .iter()
+            .enumerate()
+            .map(|(index, volume)| {
+                let name = if named {
+                    self.channel_positions[index].clone()
+                } else {
+                    index.to_string()
+                };
+                (name, *volume)
+            })
+            .collect()
+    }
+    pub fn mute(&self) -> Option<bool> {
+        self.mute
+    }
+    /// The sample format, such as `S24_32LE` or `F32LE`: the negotiated one while the
+    /// node runs, otherwise the one it prefers in its `EnumFormat` params.
+    pub fn sample_format(&self) -> Option<&str> {
+        self.sample_format.as_deref()
+    }
+    /// Whether the node can run at `rate`, or `None` if it advertised no rates.
+    pub fn supports_rate(&self, rate: u32) -> Option<bool> {
+        if self.rates.is_empty() {
+            return None;
+        }
+        Some(self.rates.contains(&rate))
+    }
+
+    /// The expected props the node did not set, and which were filled in with defaults.
+    pub fn missing_props(&self) -> &[&'static str] {
+        &self.missing_props
+    }
+
+    /// Every prop of the node, including the ones without a typed getter.
+    pub fn props(&self) -> &BTreeMap<String, String> {
+        &self.props
+    }
+    pub fn prop(&self, key: &str) -> Option<&str> {
+        self.props.get(key).map(String::as_str)
+    }
+    /// The items of a list prop such as `audio.allowed-rates = [ 44100 48000 ]`, or
+    /// `audio.position = FL,FR`. A scalar prop is a list of one.
+    pub fn prop_list(&self, key: &str) -> Option<Vec<&str>> {
+        self.prop(key).map(prop_list)
+    }
+    /// The bounds of a range prop, written as an SPA JSON object such as
+    /// `{ min = 44100 max = 192000 }`. `None` if the prop is missing or is not such a
+    /// range; a list such as `[ 44100 48000 ]` is not one, see
+    /// [`prop_list`](Self::prop_list).
+    pub fn prop_range(&self, key: &str) -> Option<RateRange> {
+        self.prop(key).and_then(RateRange::parse)
+    }
+
+    /// Whether the `node.name` or the description matches the glob `pattern`.
+    pub fn matches_name(&self, pattern: &str) -> bool {
+        glob_match(pattern, &self.node_name) || glob_match(pattern, &self.description)
+    }
+
+    /// Build a device from the props of a node info event, or `None` if the
+    /// node is not an audio sink or source, or when `class` is given, if its
+    /// `media.class` does not match that glob.
+    pub(crate) fn from_props(id: u32, props: &DictRef, class: Option<&str>) -> Option<Self> {
+        let media_class = props.get("media.class")?;
+        let direction = match class {
+            None => Direction::from_media_class(media_class)?,
+            Some(class) if glob_match(class, media_class) => {
+                Direction::from_any_media_class(media_class)
+            }
+            Some(_) => return None,
+        };
+        // Only streams say what they play; the rest keep these `None`.
+        let stream_prop = |key: &str| {
+            media_class
+                .starts_with("Stream/")
+                .then(|| props.get(key).map(str::to_owned))
+                .flatten()
+        };
+        let missing_props = EXPECTED_PROPS
+            .into_iter()
+            .filter(|key| props.get(key).is_none())
+            .collect();
+        let node_name = props.get("node.name").unwrap_or("unknown").to_owned();
+        let nick_name = props.get("node.nick").unwrap_or("unknown").to_owned();
+        let description = props
+            .get("node.description")
+            .unwrap_or("unknown")
+            .to_owned();
+        let channels: usize = props
+            .get("audio.channels")
+            .and_then(|channels| channels.parse().ok())
+            .unwrap_or(2);
+        let (channel_positions, channel_positions_raw) = props
+            .get("audio.position")
+            .map(channel_positions)
+            .unwrap_or_default();
+        let limit_quantum: u32 = props
+            .get("clock.quantum-limit")
+            .and_then(|channels| channels.parse().ok())
+            .unwrap_or(0);
+        let requested_quantum = props
+            .get("node.latency")
+            .and_then(parse_latency_quantum)
+            .or_else(|| {
+                props
+                    .get("api.alsa.period-size")
+                    .and_then(|size| size.parse().ok())
+            })
+            .filter(|quantum| *quantum != 0);
+        // Prefer the explicit props and fall back to the `hw:CARD,DEVICE` form of the pcm path.
+        let alsa_path = props.get("api.alsa.path").and_then(parse_alsa_path);
+        let alsa_card = props
+            .get("alsa.card")
+            .and_then(|card| card.parse().ok())
+            .or(alsa_path.map(|(card, _)| card));
+        let alsa_device = props
+            .get("alsa.device")
+            .and_then(|device| device.parse().ok())
+            .or(alsa_path.and_then(|(_, device)| device));
+        Some(Device {
+            id,
+            serial: props
+                .get("object.serial")
+                .and_then(|serial| serial.parse().ok()),
+            node_name,
+            nick_name,
+            description,
+            media_class: media_class.to_owned(),
+            direction,
+            channels,
+            channel_positions,
+            channel_positions_raw,
+            limit_quantum,
+            requested_quantum,
+            alsa_card,
+            alsa_device,
+            group: props.get("node.group").map(str::to_owned),
+            card: props.get("device.id").and_then(|id| id.parse().ok()),
+            card_name: props
+                .get("alsa.card_name")
+                .or_else(|| props.get("device.description"))
+                .map(str::to_owned),
+            api: props.get("device.api").map(str::to_owned),
+            factory_name: props.get("factory.name").map(str::to_owned),
+            preferred_rate: props.get("node.rate").and_then(parse_rate_fraction),
+            media_name: stream_prop("media.name"),
+            media_title: stream_prop("media.title"),
+            media_category: props.get("media.category").map(str::to_owned),
+            node_virtual: props.get("node.virtual") == Some("true"),
+            allowed_rates: props
+                .get("audio.allowed-rates")
+                .and_then(parse_numbers)
+                .unwrap_or_default(),
+            missing_props,
+            props: props
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// A copy without the fields [`InitResult::semantically_eq`](crate::InitResult::semantically_eq)
+    /// takes as volatile, reset to their defaults.
+    pub(crate) fn without_volatile(&self) -> Device {
+        Device {
+            id: 0,
+            serial: None,
+            card: None,
+            monitor_source_id: None,
+            members: vec![],
+            in_use: false,
+            running_rate: None,
+            sample_format: None,
+            volumes: vec![],
+            mute: None,
+            media_name: None,
+            media_title: None,
+            missing_props: vec![],
+            props: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// The node names more than one of `devices` share, ordered by name, which makes
+    /// selecting by name ambiguous for them.
+    pub fn name_collisions(devices: &[Device]) -> Vec<NameCollision> {
+        let mut ids: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+        for device in devices {
+            ids.entry(&device.node_name).or_default().push(device.id);
+        }
+        ids.into_iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|(node_name, ids)| NameCollision {
+                node_name: node_name.to_owned(),
+                ids,
+            })
+            .collect()
+    }
+
+    /// Fill in [`monitor_source_id`](Self::monitor_source_id) for every sink in `devices`.
+    pub(crate) fn link_monitors(devices: &mut [Device]) {
+        let sources: Vec<(String, u32)> = devices
+            .iter()
+            .filter(|device| device.direction == Direction::Output)
+            .map(|device| (device.node_name.clone(), device.id))
+            .collect();
+        for device in devices
+            .iter_mut()
+            .filter(|device| device.direction == Direction::Input)
+        {
+            let monitor = format!("{}.monitor", device.node_name);
+            device.monitor_source_id = sources
+                .iter()
+                .find(|(name, _)| *name == monitor)
+                .map(|(_, id)| *id);
+        }
+    }
+
+    /// Fill in [`members`](Self::members) and [`in_use`](Self::in_use) for every
+    /// device in `devices`.
+    pub(crate) fn link_graph(devices: &mut [Device], graph: &Graph) {
+        for device in devices {
+            device.members = graph.members(device.id);
+            device.in_use = graph.in_use(device.id, device.direction == Direction::Input);
+        }
+    }
+
+    pub(crate) fn set_props(&mut self, props: BTreeMap<String, String>) {
+        self.missing_props = EXPECTED_PROPS
+            .into_iter()
+            .filter(|key| !props.contains_key(*key))
+            .collect();
+        self.props = props;
+    }
+
+    pub(crate) fn set_permissions(&mut self, permissions: String) {
+        self.permissions = Some(permissions);
+    }
+
+    pub(crate) fn set_volume(&mut self, volume: Volume) {
+        self.volumes = volume.channels;
+        self.mute = volume.mute;
+    }
+
+    pub(crate) fn set_running_rate(&mut self, rate: u32) {
+        self.running_rate = Some(rate);
+    }
+
+    pub(crate) fn set_sample_format(&mut self, format: String) {
+        self.sample_format = Some(format);
+    }
+
+    pub(crate) fn set_rates(&mut self, mut rates: Vec<u32>) {
+        rates.sort_unstable();
+        rates.dedup();
+        self.rates = rates;
+    }
+
+    pub(crate) fn apply_settings(&mut self, settings: &Settings) {
+        self.rate = settings.rate;
+        self.allow_rates = settings.allow_rates.clone();
+        self.quantum = settings.quantum;
+        self.min_quantum = settings.min_quantum;
+        self.max_quantum = settings.max_quantum;
+    }
+}
+
+/// The items of a numeric list prop, sorted. `None` if an item is not a number.
+fn parse_numbers(value: &str) -> Option<Vec<u32>> {
+    let mut values: Vec<u32> = prop_list(value)
+        .into_iter()
+        .map(|item| item.parse().ok())
+        .collect::<Option<_>>()?;
+    values.sort_unstable();
+    values.dedup();
+    Some(values)
+}
+
+/// The bounds of a range prop, see [`Device::prop_range`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateRange {
+    min: u32,
+    max: u32,
+}
+
+impl RateRange {
+    /// Parse `{ min = 44100 max = 192000 }`, also accepting the JSON form
+    /// `{ "min": 44100, "max": 192000 }`. Both bounds must be there.
+    fn parse(value: &str) -> Option<Self> {
+        let body = value.trim().strip_prefix('{')?.strip_suffix('}')?;
+        let mut items = body
+            .split(|c: char| c == ',' || c == ':' || c == '=' || c.is_whitespace())
+            .map(|item| item.trim_matches('"'))
+            .filter(|item| !item.is_empty());
+        let (mut min, mut max) = (None, None);
+        while let Some(key) = items.next() {
+            let value = items.next()?.parse().ok()?;
+            match key {
+                "min" => min = Some(value),
+                "max" => max = Some(value),
+                _ => return None,
+            }
+        }
+        let (min, max) = (min?, max?);
+        (min <= max).then_some(Self { min, max })
+    }
+
+    pub fn min(&self) -> u32 {
+        self.min
+    }
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+    /// Whether `value` lies between [`min`](Self::min) and [`max`](Self::max), both
+    /// included.
+    pub fn contains(&self, value: u32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// A node name that several devices share, see [`Device::name_collisions`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct NameCollision {
+    node_name: String,
+    ids: Vec<u32>,
+}
+
+impl NameCollision {
+    pub fn node_name(&self) -> &str {
+        &self.node_name
+    }
+    /// The ids of the devices with the name, in the order they were listed.
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+}
+
+/// Builds a [`Device`], see [`Device::builder`].
+#[derive(Debug, Clone)]
+pub struct DeviceBuilder(Device);
+
+impl DeviceBuilder {
+    pub fn serial(mut self, serial: u64) -> Self {
+        self.0.serial = Some(serial);
+        self
+    }
+    pub fn node_name(mut self, node_name: &str) -> Self {
+        self.0.node_name = node_name.to_owned();
+        self
+    }
+    pub fn nick_name(mut self, nick_name: &str) -> Self {
+        self.0.nick_name = nick_name.to_owned();
+        self
+    }
+    pub fn description(mut self, description: &str) -> Self {
+        self.0.description = description.to_owned();
+        self
+    }
+    /// Also sets the direction, from the `Sink` or `Source` suffix.
+    pub fn media_class(mut self, media_class: &str) -> Self {
+        self.0.media_class = media_class.to_owned();
+        self.0.direction = Direction::from_any_media_class(media_class);
+        self
+    }
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.0.direction = direction;
+        self
+    }
+    pub fn channels(mut self, channels: usize) -> Self {
+        self.0.channels = channels;
+        self
+    }
+    pub fn api(mut self, api: &str) -> Self {
+        self.0.api = Some(api.to_owned());
+        self
+    }
+    pub fn rates(mut self, rates: Vec<u32>) -> Self {
+        self.0.set_rates(rates);
+        self
+    }
+    pub fn sample_format(mut self, format: &str) -> Self {
+        self.0.sample_format = Some(format.to_owned());
+        self
+    }
+    pub fn volumes(mut self, volumes: Vec<f32>, mute: Option<bool>) -> Self {
+        self.0.volumes = volumes;
+        self.0.mute = mute;
+        self
+    }
+    /// Take over the clock values of `settings`, as a snapshot does.
+    pub fn settings(mut self, settings: &Settings) -> Self {
+        self.0.apply_settings(settings);
+        self
+    }
+    /// Set a raw prop, as read by [`Device::prop`].
+    pub fn prop(mut self, key: &str, value: &str) -> Self {
+        self.0.props.insert(key.to_owned(), value.to_owned());
+        self
+    }
+    pub fn build(self) -> Device {
+        self.0
+    }
+}
+
+/// Parse an ALSA pcm path such as `hw:0,3` or `front:1` into its card and device numbers.
+fn parse_alsa_path(path: &str) -> Option<(i32, Option<i32>)> {
+    let (_, args) = path.split_once(':')?;
+    let mut args = args.split(',');
+    let card = args.next()?.parse().ok()?;
+    let device = args.next().and_then(|device| device.parse().ok());
+    Some((card, device))
+}
+
+/// Parse the numerator of a `node.latency` fraction such as `1024/48000`.
+fn parse_latency_quantum(latency: &str) -> Option<u32> {
+    let (quantum, _) = latency.split_once('/')?;
+    quantum.trim().parse().ok()
+}
+
+/// Parse the denominator of a `node.rate` fraction such as `1/48000`.
+fn parse_rate_fraction(rate: &str) -> Option<u32> {
+    let (_, denom) = rate.split_once('/')?;
+    denom.trim().parse().ok().filter(|rate| *rate != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_display_matches_serde() {
+        for direction in [Direction::Input, Direction::Output] {
+            let json = serde_json::to_value(direction).unwrap();
+            assert_eq!(json, serde_json::Value::from(direction.to_string()));
+        }
+    }
+
+    #[test]
+    fn direction_maps_to_sink_and_source() {
+        assert_eq!(
+            serde_json::to_string(&Direction::Input).unwrap(),
+            r#""sink""#
+        );
+        assert_eq!(
+            serde_json::to_string(&Direction::Output).unwrap(),
+            r#""source""#
+        );
+        let parsed: Direction = serde_json::from_str(r#""source""#).unwrap();
+        assert_eq!(parsed, Direction::Output);
+        assert!(serde_json::from_str::<Direction>(r#""Input""#).is_err());
+    }
+
+    #[test]
+    fn only_range_props_give_a_range() {
+        let device = Device::builder(40)
+            .prop("audio.allowed-rates", "[ 96000 44100 48000 ]")
+            .prop("audio.position", "FL,FR")
+            .prop("node.name", "alsa_output.pci")
+            .prop("api.rate-range", "{ min = 44100 max = 192000 }")
+            .prop("api.json-range", r#"{ "min": 8000, "max": 48000 }"#)
+            .build();
+        let range = device.prop_range("api.rate-range").unwrap();
+        assert_eq!((range.min(), range.max()), (44100, 192000));
+        assert!(range.contains(88200) && !range.contains(384000));
+        let range = device.prop_range("api.json-range").unwrap();
+        assert_eq!((range.min(), range.max()), (8000, 48000));
+        assert_eq!(device.prop_range("audio.allowed-rates"), None);
+        let allowed = parse_numbers(device.prop("audio.allowed-rates").unwrap()).unwrap();
+        assert_eq!(allowed, [44100, 48000, 96000]);
+        assert!(!allowed.contains(&88200));
+        assert_eq!(device.prop_list("audio.position"), Some(vec!["FL", "FR"]));
+        assert_eq!(device.prop_range("audio.position"), None);
+        assert_eq!(device.prop_range("node.name"), None);
+        assert_eq!(device.prop_range("audio.rate"), None);
+    }
+
+    #[test]
+    fn name_collisions_list_only_shared_names() {
+        let devices: Vec<Device> = [(40, "hdmi"), (41, "usb"), (57, "hdmi")]
+            .into_iter()
+            .map(|(id, name)| Device::builder(id).node_name(name).build())
+            .collect();
+        let collisions = Device::name_collisions(&devices);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].node_name(), "hdmi");
+        assert_eq!(collisions[0].ids(), [40, 57]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use pwtrain::{Device, Direction, PwEvent};
+
+/// The device filters given on the command line, shared by the listing and by
+/// `--watch-filter`. Unset filters let every device through.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub direction: Option<Direction>,
+    /// A glob matched against the node name and the description.
+    pub name: Option<String>,
+    /// Props that must have exactly these values.
+    pub props: Vec<(String, String)>,
+    pub category: Option<String>,
+    /// Leave out devices without a node.name.
+    pub skip_unnamed: bool,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, device: &Device) -> bool {
+        self.direction
+            .is_none_or(|direction| device.direction() == direction)
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|pattern| device.matches_name(pattern))
+            && self
+                .props
+                .iter()
+                .all(|(key, value)| device.prop(key) == Some(value.as_str()))
+            && self
+                .category
+                .as_deref()
+                .is_none_or(|category| device.media_category() == Some(category))
+            && !(self.skip_unnamed && device.missing_props().contains(&"node.name"))
+    }
+}
+
+/// Applies a [`DeviceFilter`] to watch events.
+///
+/// A removal carries only the node id, so it passes if the device matched the last
+/// time it was seen. Events that are not about one device always pass, with the
+/// device lists they carry filtered.
+pub struct EventFilter {
+    filter: DeviceFilter,
+    matched: HashSet<u32>,
+}
+
+impl EventFilter {
+    pub fn new(filter: DeviceFilter) -> Self {
+        Self {
+            filter,
+            matched: HashSet::new(),
+        }
+    }
+
+    /// `event` as far as it is about matching devices, or `None` if it is not at all.
+    pub fn apply(&mut self, event: PwEvent) -> Option<PwEvent> {
+        match event {
+            PwEvent::DeviceAdded(ref device) | PwEvent::DeviceChanged(ref device, _) => {
+                if self.filter.matches(device) {
+                    self.matched.insert(device.id());
+                    Some(event)
+                } else {
+                    self.matched.remove(&device.id());
+                    None
+                }
+            }
+            PwEvent::DeviceRemoved(id) => self.matched.remove(&id).then_some(event),
+            PwEvent::Reconnected(devices) => Some(PwEvent::Reconnected(self.keep(devices))),
+            PwEvent::Snapshot(devices, settings) => {
+                Some(PwEvent::Snapshot(self.keep(devices), settings))
+            }
+            PwEvent::SettingsChanged(_) | PwEvent::Synced => Some(event),
+        }
+    }
+
+    fn keep(&self, devices: Vec<Device>) -> Vec<Device> {
+        devices
+            .into_iter()
+            .filter(|device| self.filter.matches(device))
+            .collect()
+    }
+}
@@ -0,0 +1,80 @@
+use std::{env, fs, io, path::PathBuf};
+
+/// A card `disable --card` switched off, remembered so that `enable` can put back
+/// the profile it was on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisabledCard {
+    /// The device it was disabled through, which is how `enable` finds it again.
+    pub node_name: String,
+    pub card_name: String,
+    pub profile: String,
+}
+
+/// `$XDG_STATE_HOME/pwtrain/disabled-cards`, by default under `~/.local/state`.
+fn path() -> Result<PathBuf, String> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").ok_or("HOME is not set")?).join(".local/state"),
+    };
+    Ok(dir.join("pwtrain").join("disabled-cards"))
+}
+
+/// One card per line, as its node name, card name and profile separated by tabs.
+fn parse(text: &str) -> Vec<DisabledCard> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let (Some(node_name), Some(card_name), Some(profile), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return None;
+            };
+            Some(DisabledCard {
+                node_name: node_name.to_owned(),
+                card_name: card_name.to_owned(),
+                profile: profile.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// The cards disabled so far. No file means none.
+pub fn load() -> Result<Vec<DisabledCard>, String> {
+    let path = path()?;
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(parse(&text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(format!("failed to read {}: {err}", path.display())),
+    }
+}
+
+pub fn save(cards: &[DisabledCard]) -> Result<(), String> {
+    let path = path()?;
+    let text: String = cards
+        .iter()
+        .map(|card| format!("{}\t{}\t{}\n", card.node_name, card.card_name, card.profile))
+        .collect();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("failed to create {}: {err}", dir.display()))?;
+    }
+    fs::write(&path, text).map_err(|err| format!("failed to write {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let cards = parse("alsa_output.usb\talsa_card.usb\toutput:analog-stereo\nbroken\n");
+        assert_eq!(
+            cards,
+            [DisabledCard {
+                node_name: "alsa_output.usb".to_owned(),
+                card_name: "alsa_card.usb".to_owned(),
+                profile: "output:analog-stereo".to_owned(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use pwtrain::{PwEvent, Settings};
+use serde::Deserialize;
+
+/// The parts of a `--format json` snapshot that drift is judged against.
+#[derive(Debug, Deserialize)]
+pub struct Baseline {
+    devices: Vec<BaselineDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BaselineDevice {
+    node_name: String,
+    rate: u32,
+    quantum: u32,
+}
+
+/// Compares the watch events against a [`Baseline`] and reports when the live state
+/// starts or stops deviating from it.
+pub struct DriftMonitor {
+    expected: BTreeSet<String>,
+    /// The clock rate and quantum of the baseline, if it had any device to take them from.
+    expected_clock: Option<(u32, u32)>,
+    devices: BTreeMap<u32, String>,
+    settings: Option<Settings>,
+    synced: bool,
+    deviations: Vec<String>,
+}
+
+impl DriftMonitor {
+    pub fn new(baseline: Baseline) -> Self {
+        let expected_clock = baseline
+            .devices
+            .first()
+            .map(|device| (device.rate, device.quantum));
+        Self {
+            expected: baseline
+                .devices
+                .into_iter()
+                .map(|device| device.node_name)
+                .collect(),
+            expected_clock,
+            devices: BTreeMap::new(),
+            settings: None,
+            synced: false,
+            deviations: vec![],
+        }
+    }
+
+    /// Feed one event. Returns what to print: the new deviations when they change,
+    /// a recovery notice once they are gone, and nothing while the state matches.
+    pub fn record(&mut self, event: &PwEvent) -> Option<String> {
+        match event {
+            PwEvent::DeviceAdded(device) | PwEvent::DeviceChanged(device, _) => {
+                self.devices
+                    .insert(device.id(), device.node_name().to_owned());
+            }
+            PwEvent::DeviceRemoved(id) => {
+                self.devices.remove(id);
+            }
+            PwEvent::SettingsChanged(settings) => self.settings = Some(settings.clone()),
+            PwEvent::Synced | PwEvent::Reconnected(_) => self.synced = true,
+            PwEvent::Snapshot(devices, settings) => {
+                self.devices = devices
+                    .iter()
+                    .map(|device| (device.id(), device.node_name().to_owned()))
+                    .collect();
+                self.settings = Some(settings.clone());
+            }
+        }
+        // Until the initial state is in, every device would look missing.
+        if !self.synced {
+            return None;
+        }
+        let deviations = self.deviations();
+        if deviations == self.deviations {
+            return None;
+        }
+        let recovered = deviations.is_empty();
+        self.deviations = deviations;
+        if recovered {
+            return Some("recovered: matches the baseline again".to_owned());
+        }
+        Some(format!("drift:\n  {}", self.deviations.join("\n  ")))
+    }
+
+    fn deviations(&self) -> Vec<String> {
+        let present: BTreeSet<&String> = self.devices.values().collect();
+        let mut deviations: Vec<String> = self
+            .expected
+            .iter()
+            .filter(|name| !present.contains(name))
+            .map(|name| format!("missing device {name}"))
+            .collect();
+        deviations.extend(
+            present
+                .iter()
+                .filter(|name| !self.expected.contains(**name))
+                .map(|name| format!("new device {name}")),
+        );
+        if let (Some((rate, quantum)), Some(settings)) = (self.expected_clock, &self.settings) {
+            if settings.rate() != rate {
+                deviations.push(format!("clock.rate {} instead of {rate}", settings.rate()));
+            }
+            if settings.quantum() != quantum {
+                deviations.push(format!(
+                    "clock.quantum {} instead of {quantum}",
+                    settings.quantum()
+                ));
+            }
+        }
+        deviations
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+use pipewire as pw;
+
+#[derive(Debug)]
+pub enum PwError {
+    /// Creating the main loop, context, core or registry failed.
+    Connect(pw::Error),
+    /// Creating or connecting a stream failed.
+    Stream(pw::Error),
+    /// Asking a factory for a new object failed.
+    Create(pw::Error),
+    /// The server did not answer within [`InitOptions::timeout`](crate::InitOptions::timeout).
+    Timeout,
+    /// The server closed the connection while [`watch`](crate::watch) was running.
+    Disconnected,
+}
+
+impl fmt::Display for PwError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PwError::Connect(err) => write!(f, "failed to connect to pipewire: {err}"),
+            PwError::Stream(err) => write!(f, "failed to set up the stream: {err}"),
+            PwError::Create(err) => write!(f, "failed to create the object: {err}"),
+            PwError::Timeout => write!(f, "timed out waiting for pipewire"),
+            PwError::Disconnected => write!(f, "lost the connection to pipewire"),
+        }
+    }
+}
+
+impl std::error::Error for PwError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PwError::Connect(err) | PwError::Stream(err) | PwError::Create(err) => Some(err),
+            PwError::Timeout | PwError::Disconnected => None,
+        }
+    }
+}
+
+impl From<pw::Error> for PwError {
+    fn from(value: pw::Error) -> Self {
+        Self::Connect(value)
+    }
+}
@@ -0,0 +1,121 @@
+use std::{cell::RefCell, rc::Rc};
+
+use pipewire::{self as pw, node::Node, properties::properties, types::ObjectType};
+use serde::Serialize;
+
+use crate::{Device, PwError, init_roundtrip, roundtrip::connect};
+
+/// A `Factory` global: something the server can create objects with, such as
+/// `adapter` for nodes or `link-factory` for links.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FactoryInfo {
+    id: u32,
+    name: String,
+    type_name: String,
+    type_version: Option<u32>,
+}
+
+impl FactoryInfo {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// The `factory.name`, which is what object creation asks for.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The `factory.type.name` of the objects it makes, such as `PipeWire:Interface:Node`.
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+    pub fn type_version(&self) -> Option<u32> {
+        self.type_version
+    }
+}
+
+/// List the factories the server offers, ordered by id.
+pub fn list_factories() -> Result<Vec<FactoryInfo>, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let factories = Rc::new(RefCell::new(vec![]));
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let factories = factories.clone();
+            move |global| {
+                if global.type_ != ObjectType::Factory {
+                    return;
+                }
+                let get = |key: &str| global.props.and_then(|props| props.get(key));
+                factories.borrow_mut().push(FactoryInfo {
+                    id: global.id,
+                    name: get("factory.name").unwrap_or("unknown").to_owned(),
+                    type_name: get("factory.type.name").unwrap_or("unknown").to_owned(),
+                    type_version: get("factory.type.version").and_then(|v| v.parse().ok()),
+                });
+            }
+        })
+        .register();
+    // The registry announces every global before answering a sync sent after it.
+    let pending = core.sync(0).expect("sync failed");
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let mut factories = factories.take();
+    factories.sort_by_key(FactoryInfo::id);
+    Ok(factories)
+}
+
+/// Create a virtual sink through the `adapter` factory backed by
+/// `support.null-audio-sink`, and look for it in a fresh snapshot.
+///
+/// The sink outlives this connection and stays until it is destroyed or the server
+/// restarts. Returns its node id, or `None` if it did not show up in the snapshot.
+/// If other nodes already had `name`, the one with the highest serial, i.e. the
+/// newest, is taken to be the new sink.
+pub fn create_null_sink(name: &str, channels: u32) -> Result<Option<u32>, PwError> {
+    let (mainloop, core, _registry) = connect()?;
+
+    let props = properties! {
+        *pw::keys::FACTORY_NAME => "support.null-audio-sink",
+        *pw::keys::NODE_NAME => name,
+        *pw::keys::MEDIA_CLASS => "Audio/Sink",
+        *pw::keys::AUDIO_CHANNELS => channels.to_string(),
+        *pw::keys::OBJECT_LINGER => "true",
+    };
+    let _node: Node = core
+        .create_object("adapter", &props)
+        .map_err(PwError::Create)?;
+    let pending = core.sync(0).expect("sync failed");
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let result = init_roundtrip()?;
+    Ok(result
+        .devices()
+        .iter()
+        .filter(|device| device.node_name() == name)
+        .max_by_key(|device| device.serial())
+        .map(Device::id))
+}
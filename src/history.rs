@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use pwtrain::{Device, PwEvent};
+
+/// Follows `clock.rate` through the watch events and records every change.
+pub struct RateHistory {
+    rate: Option<u32>,
+    devices: BTreeMap<u32, Device>,
+    log: Option<File>,
+}
+
+impl RateHistory {
+    /// Start a history, appending a CSV row per change to `log_file` if given.
+    pub fn new(log_file: Option<&Path>) -> io::Result<Self> {
+        let log = match log_file {
+            Some(path) => {
+                let mut file = File::options().create(true).append(true).open(path)?;
+                if file.metadata()?.len() == 0 {
+                    writeln!(file, "timestamp_ms,rate,previous_rate,requested_by")?;
+                }
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Self {
+            rate: None,
+            devices: BTreeMap::new(),
+            log,
+        })
+    }
+
+    /// Feed one event. Returns a description of the rate change, if it was one.
+    pub fn record(&mut self, event: &PwEvent) -> Option<String> {
+        let settings = match event {
+            PwEvent::DeviceAdded(device) | PwEvent::DeviceChanged(device, _) => {
+                self.devices.insert(device.id(), device.clone());
+                return None;
+            }
+            PwEvent::DeviceRemoved(id) => {
+                self.devices.remove(id);
+                return None;
+            }
+            PwEvent::SettingsChanged(settings) => settings,
+            PwEvent::Synced | PwEvent::Reconnected(_) | PwEvent::Snapshot(..) => return None,
+        };
+        let rate = settings.rate();
+        let previous = self.rate.replace(rate);
+        if previous == Some(rate) {
+            return None;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or_default();
+        // The server does not say who caused a switch, so name the nodes asking for this rate.
+        let requested_by: Vec<&str> = self
+            .devices
+            .values()
+            .filter(|device| device.preferred_rate() == Some(rate))
+            .map(Device::node_name)
+            .collect();
+        let previous = previous.map(|rate| rate.to_string()).unwrap_or_default();
+        if let Some(log) = &mut self.log {
+            let _ = writeln!(
+                log,
+                "{timestamp},{rate},{previous},{}",
+                requested_by.join(" ")
+            );
+        }
+        let mut line = format!("{timestamp} clock.rate {previous} -> {rate}");
+        if !requested_by.is_empty() {
+            line.push_str(&format!(" (requested by {})", requested_by.join(", ")));
+        }
+        Some(line)
+    }
+}
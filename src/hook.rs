@@ -0,0 +1,91 @@
+use std::{
+    collections::BTreeMap,
+    process::{Child, Command},
+};
+
+use pwtrain::{Device, PwEvent};
+
+/// Runs a shell command for every watch event, with the event in its environment.
+pub struct ChangeHook {
+    command: String,
+    running: Option<Child>,
+    /// Removal events only carry the id, so the devices are kept to name them.
+    devices: BTreeMap<u32, Device>,
+}
+
+impl ChangeHook {
+    pub fn new(command: String) -> Self {
+        Self {
+            command,
+            running: None,
+            devices: BTreeMap::new(),
+        }
+    }
+
+    /// Start the command for `event`, unless the one for an earlier event is still
+    /// running, in which case `event` is dropped. Returns a warning to show if the
+    /// command could not be run or was skipped.
+    pub fn run(&mut self, event: &PwEvent) -> Option<String> {
+        let mut env: Vec<(&str, String)> = vec![];
+        let device = match event {
+            PwEvent::DeviceAdded(device) => {
+                self.devices.insert(device.id(), device.clone());
+                env.push(("PW_EVENT_TYPE", "device-added".to_owned()));
+                Some(device.clone())
+            }
+            PwEvent::DeviceChanged(device, _) => {
+                self.devices.insert(device.id(), device.clone());
+                env.push(("PW_EVENT_TYPE", "device-changed".to_owned()));
+                Some(device.clone())
+            }
+            PwEvent::DeviceRemoved(id) => {
+                env.push(("PW_EVENT_TYPE", "device-removed".to_owned()));
+                env.push(("PW_DEVICE_ID", id.to_string()));
+                self.devices.remove(id)
+            }
+            PwEvent::SettingsChanged(settings) => {
+                env.push(("PW_EVENT_TYPE", "settings-changed".to_owned()));
+                env.push(("PW_CLOCK_RATE", settings.rate().to_string()));
+                env.push(("PW_CLOCK_QUANTUM", settings.quantum().to_string()));
+                None
+            }
+            PwEvent::Reconnected(_) => {
+                env.push(("PW_EVENT_TYPE", "reconnected".to_owned()));
+                None
+            }
+            PwEvent::Snapshot(_, settings) => {
+                env.push(("PW_EVENT_TYPE", "snapshot".to_owned()));
+                env.push(("PW_CLOCK_RATE", settings.rate().to_string()));
+                env.push(("PW_CLOCK_QUANTUM", settings.quantum().to_string()));
+                None
+            }
+            PwEvent::Synced => return None,
+        };
+        if let Some(device) = device {
+            env.extend([
+                ("PW_DEVICE_ID", device.id().to_string()),
+                ("PW_DEVICE_NAME", device.node_name().to_owned()),
+                ("PW_DEVICE_DESCRIPTION", device.description().to_owned()),
+                ("PW_DEVICE_DIRECTION", device.direction_str().to_owned()),
+            ]);
+        }
+
+        if let Some(child) = &mut self.running
+            && matches!(child.try_wait(), Ok(None))
+        {
+            return Some("--on-change command still running, event skipped".to_owned());
+        }
+        match Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .envs(env)
+            .spawn()
+        {
+            Ok(child) => {
+                self.running = Some(child);
+                None
+            }
+            Err(err) => Some(format!("failed to run the --on-change command: {err}")),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc};
+
+use pipewire::{
+    self as pw,
+    device::Device as DeviceProxy,
+    metadata::Metadata,
+    node::Node,
+    proxy::{Proxy, ProxyT},
+    registry::GlobalObject,
+    spa::{param::ParamType, pod::deserialize::PodDeserializer, utils::dict::DictRef},
+    types::ObjectType,
+};
+
+use crate::{
+    PwError,
+    roundtrip::{Request, connect},
+    state::Pending,
+};
+
+/// Every param [`inspect`] asks a node or device for. The ones an object does not
+/// have are left out of the result.
+const PARAM_TYPES: [(ParamType, &str); 16] = [
+    (ParamType::PropInfo, "PropInfo"),
+    (ParamType::Props, "Props"),
+    (ParamType::EnumFormat, "EnumFormat"),
+    (ParamType::Format, "Format"),
+    (ParamType::Buffers, "Buffers"),
+    (ParamType::Meta, "Meta"),
+    (ParamType::IO, "IO"),
+    (ParamType::EnumProfile, "EnumProfile"),
+    (ParamType::Profile, "Profile"),
+    (ParamType::EnumPortConfig, "EnumPortConfig"),
+    (ParamType::PortConfig, "PortConfig"),
+    (ParamType::EnumRoute, "EnumRoute"),
+    (ParamType::Route, "Route"),
+    (ParamType::Control, "Control"),
+    (ParamType::Latency, "Latency"),
+    (ParamType::ProcessLatency, "ProcessLatency"),
+];
+
+/// A port of an inspected node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InspectedPort {
+    id: u32,
+    node: Option<u32>,
+    name: String,
+    direction: String,
+}
+
+impl InspectedPort {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// The `port.name`, such as `playback_FL`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The `port.direction`: `in` or `out`.
+    pub fn direction(&self) -> &str {
+        &self.direction
+    }
+}
+
+/// One property of an inspected metadata object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataEntry {
+    subject: u32,
+    key: String,
+    type_: Option<String>,
+    value: String,
+}
+
+impl MetadataEntry {
+    /// The id of the object the property is about, 0 for global ones.
+    pub fn subject(&self) -> u32 {
+        self.subject
+    }
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// The type the value was written with, such as `Spa:String:JSON`.
+    pub fn type_(&self) -> Option<&str> {
+        self.type_.as_deref()
+    }
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Everything one node, device or metadata object tells about itself, as read by
+/// [`inspect`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inspection {
+    id: u32,
+    kind: String,
+    props: BTreeMap<String, String>,
+    params: Vec<(String, String)>,
+    ports: Vec<InspectedPort>,
+    metadata: Vec<MetadataEntry>,
+}
+
+impl Inspection {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// The object type: `Node`, `Device` or `Metadata`.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+    /// The props of the global, updated with the fuller set the object sends once bound.
+    pub fn props(&self) -> &BTreeMap<String, String> {
+        &self.props
+    }
+    /// Every param the object enumerated, as its type name and the pretty-printed pod,
+    /// in the order the server sent them.
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+    /// For a node, its ports, ordered by id.
+    pub fn ports(&self) -> &[InspectedPort] {
+        &self.ports
+    }
+    /// For a metadata object, its properties in the order the server sent them.
+    pub fn metadata(&self) -> &[MetadataEntry] {
+        &self.metadata
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.kind, self.id)?;
+        writeln!(f, "\nprops:")?;
+        for (key, value) in &self.props {
+            writeln!(f, "  {key} = {value:?}")?;
+        }
+        if !self.ports.is_empty() {
+            writeln!(f, "\nports:")?;
+            for port in &self.ports {
+                writeln!(f, "  {} {} ({})", port.id, port.name, port.direction)?;
+            }
+        }
+        if !self.metadata.is_empty() {
+            writeln!(f, "\nmetadata:")?;
+            for entry in &self.metadata {
+                let type_ = entry.type_.as_deref().unwrap_or("-");
+                writeln!(
+                    f,
+                    "  {} {} ({type_}) = {}",
+                    entry.subject, entry.key, entry.value
+                )?;
+            }
+        }
+        for (name, value) in &self.params {
+            writeln!(f, "\nparam {name}:")?;
+            for line in value.lines() {
+                writeln!(f, "  {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn dict_map(props: &DictRef) -> BTreeMap<String, String> {
+    props
+        .iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Whether `global` is the object `target` names, by id or by its node, device or
+/// metadata name.
+fn is_target(global: &GlobalObject<&DictRef>, target: &str) -> bool {
+    if let Ok(id) = target.parse::<u32>() {
+        return global.id == id;
+    }
+    let key = match global.type_ {
+        ObjectType::Node => "node.name",
+        ObjectType::Device => "device.name",
+        ObjectType::Metadata => "metadata.name",
+        _ => return false,
+    };
+    global.props.and_then(|props| props.get(key)) == Some(target)
+}
+
+/// Bind the node, device or metadata object `target` names, by id or by name, and
+/// read everything it reports: its props, every param it enumerates, the ports of a
+/// node and the properties of a metadata object.
+///
+/// Returns `None` when no such object exists. If several share the name, the first
+/// one announced is taken.
+pub fn inspect(target: &str) -> Result<Option<Inspection>, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let inspection: Rc<RefCell<Option<Inspection>>> = Rc::new(RefCell::new(None));
+    // Ports are announced on their own, possibly before their node.
+    let ports: Rc<RefCell<Vec<InspectedPort>>> = Rc::new(RefCell::new(vec![]));
+    let bound: Rc<RefCell<Option<(Proxy, Request)>>> = Rc::new(RefCell::new(None));
+    let peddings = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+    peddings.push(pending);
+
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let peddings = peddings.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                if peddings.complete(seq) == Some(0) {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    let target = target.to_owned();
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let registry = registry.clone();
+            let inspection = inspection.clone();
+            let ports = ports.clone();
+            let core = core.clone();
+            move |global| {
+                if global.type_ == ObjectType::Port {
+                    let get = |key: &str| global.props.and_then(|props| props.get(key));
+                    ports.borrow_mut().push(InspectedPort {
+                        id: global.id,
+                        node: get("node.id").and_then(|id| id.parse().ok()),
+                        name: get("port.name").unwrap_or_default().to_owned(),
+                        direction: get("port.direction").unwrap_or_default().to_owned(),
+                    });
+                }
+                if bound.borrow().is_some() || !is_target(global, &target) {
+                    return;
+                }
+                let kind = match &global.type_ {
+                    ObjectType::Other(other) => other.clone(),
+                    known => format!("{known:?}"),
+                };
+                inspection.replace(Some(Inspection {
+                    id: global.id,
+                    kind,
+                    props: global.props.map(dict_map).unwrap_or_default(),
+                    ..Default::default()
+                }));
+                let on_param = {
+                    let inspection = inspection.clone();
+                    move |_, param_type: ParamType, _, _, pod: Option<&pw::spa::pod::Pod>| {
+                        let Some(Ok((_, value))) =
+                            pod.map(|pod| PodDeserializer::deserialize_any_from(pod.as_bytes()))
+                        else {
+                            return;
+                        };
+                        let name = PARAM_TYPES
+                            .iter()
+                            .find(|(known, _)| *known == param_type)
+                            .map(|(_, name)| (*name).to_owned())
+                            .unwrap_or_else(|| param_type.as_raw().to_string());
+                        if let Some(inspection) = inspection.borrow_mut().as_mut() {
+                            inspection.params.push((name, format!("{value:#?}")));
+                        }
+                    }
+                };
+                let on_props = {
+                    let inspection = inspection.clone();
+                    move |props: Option<&DictRef>| {
+                        if let (Some(inspection), Some(props)) =
+                            (inspection.borrow_mut().as_mut(), props)
+                        {
+                            inspection.props.extend(dict_map(props));
+                        }
+                    }
+                };
+                let request = match global.type_ {
+                    ObjectType::Node => {
+                        let Ok(node) = registry.bind::<Node, _>(global) else {
+                            return;
+                        };
+                        let listener = node
+                            .add_listener_local()
+                            .info(move |info| on_props(info.props()))
+                            .param(on_param)
+                            .register();
+                        for (param_type, _) in PARAM_TYPES {
+                            node.enum_params(0, Some(param_type), 0, u32::MAX);
+                        }
+                        (node.upcast(), listener.into())
+                    }
+                    ObjectType::Device => {
+                        let Ok(device) = registry.bind::<DeviceProxy, _>(global) else {
+                            return;
+                        };
+                        let listener = device
+                            .add_listener_local()
+                            .info(move |info| on_props(info.props()))
+                            .param(on_param)
+                            .register();
+                        for (param_type, _) in PARAM_TYPES {
+                            device.enum_params(0, Some(param_type), 0, u32::MAX);
+                        }
+                        (device.upcast(), listener.into())
+                    }
+                    ObjectType::Metadata => {
+                        let Ok(metadata) = registry.bind::<Metadata, _>(global) else {
+                            return;
+                        };
+                        let inspection = inspection.clone();
+                        let listener = metadata
+                            .add_listener_local()
+                            .property(move |subject, key, type_, value| {
+                                let (Some(key), Some(value)) = (key, value) else {
+                                    return 0;
+                                };
+                                if let Some(inspection) = inspection.borrow_mut().as_mut() {
+                                    inspection.metadata.push(MetadataEntry {
+                                        subject,
+                                        key: key.to_owned(),
+                                        type_: type_.map(str::to_owned),
+                                        value: value.to_owned(),
+                                    });
+                                }
+                                0
+                            })
+                            .register();
+                        (metadata.upcast(), listener.into())
+                    }
+                    _ => return,
+                };
+                bound.replace(Some(request));
+                let pending = core.sync(0).expect("sync failed");
+                peddings.push(pending);
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let Some(mut inspection) = inspection.take() else {
+        return Ok(None);
+    };
+    let mut ports: Vec<InspectedPort> = ports
+        .take()
+        .into_iter()
+        .filter(|port| port.node == Some(inspection.id))
+        .collect();
+    ports.sort_by_key(|port| port.id);
+    inspection.ports = ports;
+    Ok(Some(inspection))
+}
@@ -0,0 +1,47 @@
+//! Query PipeWire for its audio sinks and sources together with the global clock settings.
+
+mod bind;
+mod card;
+mod device;
+mod error;
+mod factory;
+mod filter;
+mod inspect;
+mod link;
+mod meter;
+mod params;
+mod probe;
+mod profile;
+mod record;
+mod roundtrip;
+mod route;
+mod session;
+mod settings;
+mod state;
+mod suspend;
+mod version;
+mod watch;
+
+pub use card::{Availability, Card, CardProfile, list_cards, set_card_profile};
+pub use device::{Device, DeviceBuilder, Direction, NameCollision, RateRange};
+pub use error::PwError;
+pub use factory::{FactoryInfo, create_null_sink, list_factories};
+pub use inspect::{InspectedPort, Inspection, MetadataEntry, inspect};
+pub use link::{LinkInfo, unlink};
+pub use meter::{Level, meter};
+pub use probe::{ProbeResult, probe_rate};
+pub use profile::{Profile, apply_profile};
+pub use record::{Recording, record};
+pub use roundtrip::{
+    Decision, InitOptions, InitResult, MetadataCheck, Progress, TimeoutAction, device_present,
+    init_roundtrip, init_roundtrip_with, set_default, set_remote, snapshot_with_progress,
+};
+pub use route::RouteSetting;
+pub use session::Session;
+pub use settings::{ExpectedSettings, Mismatch, Settings};
+pub use suspend::suspend_node;
+pub use version::{library_version, server_version};
+pub use watch::{
+    DeviceChange, PwEvent, RECONNECT_DELAY, StopSignal, WatchOptions, read_settings, wait_settle,
+    watch,
+};
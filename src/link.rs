@@ -0,0 +1,276 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    rc::Rc,
+};
+
+use pipewire::{self as pw, registry::GlobalObject, spa::utils::dict::DictRef, types::ObjectType};
+use serde::{Deserialize, Serialize};
+
+use crate::{PwError, roundtrip::connect};
+
+/// A connection between an output port and an input port.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkInfo {
+    id: u32,
+    output_node: u32,
+    output_port: u32,
+    input_node: u32,
+    input_port: u32,
+    output_node_name: String,
+    output_port_name: String,
+    input_node_name: String,
+    input_port_name: String,
+}
+
+impl LinkInfo {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    pub fn output_node(&self) -> u32 {
+        self.output_node
+    }
+    pub fn output_port(&self) -> u32 {
+        self.output_port
+    }
+    pub fn input_node(&self) -> u32 {
+        self.input_node
+    }
+    pub fn input_port(&self) -> u32 {
+        self.input_port
+    }
+    pub fn output_node_name(&self) -> &str {
+        &self.output_node_name
+    }
+    pub fn output_port_name(&self) -> &str {
+        &self.output_port_name
+    }
+    pub fn input_node_name(&self) -> &str {
+        &self.input_node_name
+    }
+    pub fn input_port_name(&self) -> &str {
+        &self.input_port_name
+    }
+
+    /// Whether the link runs from `output` to `input`, each given either as a node
+    /// name or as `node:port`.
+    pub fn connects(&self, output: &str, input: &str) -> bool {
+        let matches = |spec: &str, node: &str, port: &str| match spec.split_once(':') {
+            Some((spec_node, spec_port)) => spec_node == node && spec_port == port,
+            None => spec == node,
+        };
+        matches(output, &self.output_node_name, &self.output_port_name)
+            && matches(input, &self.input_node_name, &self.input_port_name)
+    }
+}
+
+impl fmt::Display for LinkInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} -> {}:{}",
+            self.output_node_name,
+            self.output_port_name,
+            self.input_node_name,
+            self.input_port_name
+        )
+    }
+}
+
+/// Node, port and link globals as announced by the registry, used to resolve the
+/// ids of a link into names.
+#[derive(Debug, Default)]
+pub(crate) struct Graph {
+    nodes: HashMap<u32, String>,
+    /// The `node.link-group` of the nodes that have one, shared by the nodes a module
+    /// such as a loopback or combine sink creates together.
+    link_groups: HashMap<u32, String>,
+    /// The nodes whose `media.class` is a `Stream/*` one, i.e. application streams.
+    streams: HashSet<u32>,
+    ports: HashMap<u32, String>,
+    links: Vec<LinkInfo>,
+}
+
+impl Graph {
+    pub(crate) fn add_global(&mut self, global: &GlobalObject<&DictRef>) {
+        let Some(props) = global.props else {
+            return;
+        };
+        let get = |key: &str| props.get(key);
+        let get_id = |key: &str| get(key).and_then(|id| id.parse().ok());
+        match global.type_ {
+            ObjectType::Node => {
+                let name = get("node.name").unwrap_or("unknown");
+                self.nodes.insert(global.id, name.to_owned());
+                if let Some(group) = get("node.link-group") {
+                    self.link_groups.insert(global.id, group.to_owned());
+                }
+                if get("media.class").is_some_and(|class| class.starts_with("Stream/")) {
+                    self.streams.insert(global.id);
+                }
+            }
+            ObjectType::Port => {
+                let name = get("audio.channel")
+                    .or_else(|| get("port.name"))
+                    .unwrap_or("unknown");
+                self.ports.insert(global.id, name.to_owned());
+            }
+            ObjectType::Link => {
+                let (Some(output_port), Some(input_port)) =
+                    (get_id("link.output.port"), get_id("link.input.port"))
+                else {
+                    return;
+                };
+                self.links.push(LinkInfo {
+                    id: global.id,
+                    output_node: get_id("link.output.node").unwrap_or_default(),
+                    output_port,
+                    input_node: get_id("link.input.node").unwrap_or_default(),
+                    input_port,
+                    ..Default::default()
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// The nodes outside the link group of `node` that the rest of the group is linked
+    /// with, such as the sinks a combine sink plays to. Links of `node` itself are
+    /// left out, since those are the clients using it.
+    pub(crate) fn members(&self, node: u32) -> Vec<u32> {
+        let Some(group) = self.link_groups.get(&node) else {
+            return vec![];
+        };
+        let in_group = |id: u32| self.link_groups.get(&id) == Some(group);
+        let mut members: Vec<u32> = self
+            .links
+            .iter()
+            .filter(|link| link.output_node != node && link.input_node != node)
+            .filter_map(
+                |link| match (in_group(link.output_node), in_group(link.input_node)) {
+                    (true, false) => Some(link.input_node),
+                    (false, true) => Some(link.output_node),
+                    _ => None,
+                },
+            )
+            .collect();
+        members.sort_unstable();
+        members.dedup();
+        members
+    }
+
+    /// Whether a stream reaches `node` through the links, directly or through nodes
+    /// in between such as a combine sink or a filter: a stream playing into it when
+    /// `upstream`, as for a sink, or one recording from it otherwise.
+    pub(crate) fn in_use(&self, node: u32, upstream: bool) -> bool {
+        let mut seen = HashSet::from([node]);
+        let mut queue = VecDeque::from([node]);
+        while let Some(current) = queue.pop_front() {
+            for link in &self.links {
+                let (from, to) = if upstream {
+                    (link.input_node, link.output_node)
+                } else {
+                    (link.output_node, link.input_node)
+                };
+                if from != current || !seen.insert(to) {
+                    continue;
+                }
+                if self.streams.contains(&to) {
+                    return true;
+                }
+                queue.push_back(to);
+            }
+        }
+        false
+    }
+
+    /// The links with their node and port names filled in.
+    pub(crate) fn into_links(self) -> Vec<LinkInfo> {
+        let name = |names: &HashMap<u32, String>, id: u32| {
+            names.get(&id).cloned().unwrap_or_else(|| id.to_string())
+        };
+        self.links
+            .into_iter()
+            .map(|link| LinkInfo {
+                output_node_name: name(&self.nodes, link.output_node),
+                output_port_name: name(&self.ports, link.output_port),
+                input_node_name: name(&self.nodes, link.input_node),
+                input_port_name: name(&self.ports, link.input_port),
+                ..link
+            })
+            .collect()
+    }
+}
+
+/// Destroy the given link globals and wait for the server to confirm.
+///
+/// Returns how many of them were actually removed.
+pub fn unlink(ids: &[u32]) -> Result<usize, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let wanted: HashSet<u32> = ids.iter().copied().collect();
+    let removed = Rc::new(RefCell::new(HashSet::new()));
+    let _listener_reg = registry
+        .add_listener_local()
+        .global_remove({
+            let removed = removed.clone();
+            move |id| {
+                if wanted.contains(&id) {
+                    removed.borrow_mut().insert(id);
+                }
+            }
+        })
+        .register();
+
+    for id in ids {
+        registry.destroy_global(*id);
+    }
+    let pending = core.sync(0).expect("sync failed");
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let removed = removed.borrow().len();
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(output_node: u32, input_node: u32) -> LinkInfo {
+        LinkInfo {
+            output_node,
+            input_node,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn in_use_follows_streams_through_other_nodes() {
+        // 70 plays into the combine sink 60, which plays to the sink 40; the
+        // source 50 is only linked to the sink 41, which nothing plays to.
+        let graph = Graph {
+            streams: HashSet::from([70]),
+            links: vec![link(70, 60), link(60, 40), link(50, 41)],
+            ..Default::default()
+        };
+        assert!(graph.in_use(40, true));
+        assert!(graph.in_use(60, true));
+        assert!(!graph.in_use(40, false));
+        assert!(!graph.in_use(41, true));
+        assert!(!graph.in_use(50, false));
+        assert!(!graph.in_use(42, true));
+    }
+}
@@ -1,308 +1,1551 @@
-use std::{cell::RefCell, rc::Rc};
-
-use pipewire::{
-    self as pw,
-    metadata::{Metadata, MetadataListener},
-    node::{Node, NodeListener},
-    proxy::ProxyT,
-    spa::utils::result::AsyncSeq,
+mod benchmark;
+mod cache;
+mod config;
+mod control;
+mod device_filter;
+mod disabled;
+mod drift;
+mod history;
+mod hook;
+mod output;
+mod recent;
+mod top;
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use pipewire as pw;
+use pwtrain::{
+    Availability, Card, Device, Direction, ExpectedSettings, InitOptions, InitResult, Level,
+    Profile, PwEvent, Session, Settings, TimeoutAction, WatchOptions, apply_profile,
+    create_null_sink, init_roundtrip_with, inspect, library_version, list_cards, list_factories,
+    meter, probe_rate, read_settings, record, server_version, set_card_profile, set_default,
+    set_remote, suspend_node, unlink, wait_settle, watch,
+};
+
+use crate::{
+    benchmark::benchmark,
+    config::Config,
+    control::{ControlState, serve},
+    device_filter::{DeviceFilter, EventFilter},
+    disabled::DisabledCard,
+    drift::{Baseline, DriftMonitor},
+    history::RateHistory,
+    hook::ChangeHook,
+    output::{
+        Format, GraphFormat, GroupBy, Snapshot, TableStyle, Template, render, render_graph,
+        use_color,
+    },
+    recent::RecentEvents,
+    top::top,
 };
 
-#[derive(Clone, Debug, Copy, Default)]
-pub enum Direction {
-    #[default]
-    Input,
-    Output,
+/// How often and how many times `disable` and `enable` look for the change to show.
+const CONFIRM_INTERVAL: Duration = Duration::from_millis(200);
+const CONFIRM_ATTEMPTS: u32 = 10;
+
+/// How long --poll-until-stable waits between snapshots.
+const STABLE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Parser, Debug)]
+#[command(about, disable_version_flag = true)]
+#[command(group(ArgGroup::new("watching").args(["watch", "watch_settings_only"])))]
+struct Cli {
+    /// Connect to this daemon socket, e.g. `pipewire-0`. Defaults to PIPEWIRE_REMOTE,
+    /// then to the usual socket.
+    #[arg(long, value_name = "NAME")]
+    remote: Option<String>,
+    /// Print the version of pwtrain, the linked libpipewire and the connected server.
+    #[arg(short = 'V', long)]
+    version: bool,
+    /// Keep running and print devices and settings as they change. Sending the
+    /// process SIGUSR1 prints every device and the settings as they are now (Unix only).
+    #[arg(long)]
+    watch: bool,
+    /// Keep running and only print the clock rate, quantum and allowed rates as they
+    /// change, without looking at any node.
+    #[arg(long)]
+    watch_settings_only: bool,
+    /// In --watch, also append every clock.rate change to this CSV file.
+    #[arg(long, value_name = "PATH", requires = "watching")]
+    log_file: Option<PathBuf>,
+    /// In --watch, run this shell command on every event, one at a time. The event is
+    /// described in PW_EVENT_TYPE, PW_DEVICE_ID, PW_DEVICE_NAME, PW_DEVICE_DESCRIPTION,
+    /// PW_DEVICE_DIRECTION, PW_CLOCK_RATE and PW_CLOCK_QUANTUM.
+    #[arg(long, value_name = "COMMAND", requires = "watching")]
+    on_change: Option<String>,
+    /// In --watch, only print events about devices that pass --direction, --name,
+    /// --category, --props-filter and --skip-unnamed.
+    #[arg(long, requires = "watch")]
+    watch_filter: bool,
+    /// In --watch, how many times in a row to try reconnecting when pipewire goes away,
+    /// e.g. because it restarted. 0 ends the watch with an error instead.
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "watching")]
+    max_reconnects: u32,
+    /// In --watch, keep the last N events in memory and print them to stderr, with
+    /// when they arrived, once the watch stops.
+    #[arg(long, value_name = "N", requires = "watching")]
+    recent: Option<usize>,
+    /// In --watch, answer `list`, `get rate`, `set rate N` and the like with a line of
+    /// JSON each on this Unix socket, so scripts can share the running watch. Since
+    /// nobody can be asked on the socket, `set` is refused without --assume-yes.
+    #[arg(long, value_name = "PATH", requires = "watch")]
+    control_socket: Option<PathBuf>,
+    /// In --watch, stay quiet unless the devices or clock settings deviate from this
+    /// `--format json` snapshot, and say when they match it again.
+    #[arg(
+        long,
+        value_name = "SNAPSHOT",
+        requires = "watch",
+        conflicts_with = "watch_settings_only"
+    )]
+    since: Option<PathBuf>,
+    /// Take a fresh snapshot every SECS seconds over one connection and print it. Only
+    /// for listings, not for commands that change the graph or capture from it.
+    #[arg(long, value_name = "SECS", conflicts_with_all = ["watching", "select"])]
+    watch_interval: Option<f64>,
+    /// Repeat the snapshot until two in a row list the same devices, for at most SECS
+    /// seconds, to catch devices that are still appearing.
+    #[arg(
+        long,
+        value_name = "SECS",
+        conflicts_with_all = ["watching", "watch_interval", "cache_ttl"]
+    )]
+    poll_until_stable: Option<f64>,
+    /// List the devices, read a choice from stdin and print the chosen device id.
+    #[arg(long, conflicts_with = "watching")]
+    select: bool,
+    /// Only list nodes whose media.class matches this glob, e.g. `Video/*`.
+    /// Defaults to audio sinks and sources.
+    #[arg(long, value_name = "GLOB")]
+    class: Option<String>,
+    /// Only list sinks or only list sources.
+    #[arg(long)]
+    direction: Option<DirectionArg>,
+    /// Only list devices with this media.category, such as Playback, Capture, Duplex
+    /// or Monitor.
+    #[arg(long, value_name = "CATEGORY")]
+    category: Option<String>,
+    /// Only list devices whose node name or description matches this glob.
+    #[arg(long, value_name = "GLOB")]
+    name: Option<String>,
+    /// Only list devices whose prop KEY equals VALUE. Repeat to require several props.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_prop_filter)]
+    props_filter: Vec<(String, String)>,
+    /// Leave out devices without a node.name, which are listed as `unknown` otherwise.
+    #[arg(long)]
+    skip_unnamed: bool,
+    /// Stop collecting after this many devices and warn that the list is incomplete.
+    #[arg(long, value_name = "N")]
+    max_devices: Option<usize>,
+    /// Also read the per-route volumes from the route-settings metadata.
+    #[arg(long)]
+    route_settings: bool,
+    /// Bind device nodes at this interface version instead of the one this build of
+    /// libpipewire uses, for servers that are missing params otherwise.
+    #[arg(long, value_name = "VERSION")]
+    node_version: Option<u32>,
+    /// Reuse the snapshot of an earlier run if it is at most SECS seconds old, and
+    /// save this run's snapshot for later ones. The snapshot is kept in
+    /// $XDG_RUNTIME_DIR; without it nothing is cached. Only for listings, not for
+    /// commands that change the graph, record or save a profile.
+    #[arg(long, value_name = "SECS")]
+    cache_ttl: Option<f64>,
+    /// With --cache-ttl, take a fresh snapshot even if the cached one would do.
+    #[arg(long, requires = "cache_ttl")]
+    no_cache: bool,
+    /// Give up waiting for the server after SECS seconds.
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<f64>,
+    /// On --timeout, fail or go on with the devices collected so far.
+    #[arg(long, requires = "timeout", default_value = "error")]
+    timeout_action: TimeoutActionArg,
+    /// Exit with status 3 when no device is left after filtering.
+    #[arg(long)]
+    fail_if_empty: bool,
+    /// Exit without calling pw_deinit, for debugging under tools or harnesses that
+    /// manage pipewire's globals themselves.
+    #[arg(long, hide = true)]
+    no_deinit: bool,
+    /// Only print the first device left after filtering, exiting with status 3 if
+    /// there is none.
+    #[arg(long, conflicts_with = "select")]
+    first: bool,
+    /// Only list the current default sink and source.
+    #[arg(long, conflicts_with_all = ["direction", "name", "props_filter", "category"])]
+    only_default: bool,
+    /// A label to include in the JSON output, e.g. to tell hosts apart.
+    #[arg(long)]
+    tag: Option<String>,
+    /// Print the devices in this format instead of the debug listing.
+    #[arg(long)]
+    format: Option<Format>,
+    /// Print a line per device from this template instead, where `{field}` is a
+    /// field by its JSON name, e.g. `"{id} {node_name} {channels}ch"`. Write `{{` and
+    /// `}}` for literal braces.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "format")]
+    output_template: Option<String>,
+    /// Also print the devices as a table on stderr, leaving stdout to --format.
+    #[arg(long)]
+    human_stderr: bool,
+    /// Section the table under a heading per direction, card or api.
+    #[arg(long)]
+    group_by: Option<GroupBy>,
+    /// In the table, cut names and descriptions to this many columns.
+    #[arg(long, value_name = "COLUMNS")]
+    max_width: Option<usize>,
+    /// Read the config from this file instead of $XDG_CONFIG_HOME/pwtrain/config. Its
+    /// `[fields]` section maps node props to extra columns, e.g. `api.alsa.path = alsa_path`.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// In JSON, keep arrays of plain values such as rates and volumes on one line.
+    #[arg(long)]
+    json_compact_arrays: bool,
+    /// Never color the output. Setting NO_COLOR does the same.
+    #[arg(long)]
+    no_color: bool,
+    /// Fail unless the clock rate is this value.
+    #[arg(long, value_name = "RATE")]
+    expect_rate: Option<u32>,
+    /// Fail unless the quantum is this value.
+    #[arg(long, value_name = "QUANTUM")]
+    expect_quantum: Option<u32>,
+    /// Print nothing unless asked for explicitly, e.g. with --format. Errors are still shown.
+    #[arg(short, long)]
+    quiet: bool,
+    /// Like --quiet, but also hide errors and only report through the exit status.
+    #[arg(long)]
+    silent: bool,
+    /// Print on stderr what was done with every global the server announced, and why.
+    #[arg(long)]
+    explain: bool,
+    /// Report nodes that lack expected props on stderr.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Do not ask before unlink, create-null-sink, disable, enable, load-profile,
+    /// default and probe change the live graph. Without it they need an interactive
+    /// terminal to ask on, and --control-socket refuses `set`.
+    #[arg(short = 'y', long)]
+    assume_yes: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    fn is_quiet(&self) -> bool {
+        self.quiet || self.silent
+    }
+    /// Whether the command only prints what a snapshot holds, which an older snapshot
+    /// can do too. Commands that change the graph or capture from it need a fresh one.
+    fn lists_only(&self) -> bool {
+        matches!(
+            self.command,
+            None | Some(
+                Command::Rates
+                    | Command::CommonRates { .. }
+                    | Command::Links
+                    | Command::Graph { .. }
+                    | Command::Groups
+            )
+        )
+    }
+    /// Reject flag combinations clap cannot express, such as a flag that conflicts
+    /// with some subcommands only.
+    fn check(&self) -> Result<(), String> {
+        if self.cache_ttl.is_some() && !self.lists_only() {
+            return Err(
+                "--cache-ttl only works for listings, this command needs a fresh snapshot"
+                    .to_owned(),
+            );
+        }
+        if self.watch_interval.is_some() && !self.lists_only() {
+            return Err(
+                "--watch-interval only works for listings, not to repeat a command \
+                 that changes the graph or captures from it"
+                    .to_owned(),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn device_filter(cli: &Cli) -> DeviceFilter {
+    DeviceFilter {
+        direction: cli.direction.map(Direction::from),
+        name: cli.name.clone(),
+        props: cli.props_filter.clone(),
+        category: cli.category.clone(),
+        skip_unnamed: cli.skip_unnamed,
+    }
+}
+
+fn parse_prop_filter(filter: &str) -> Result<(String, String), String> {
+    let (key, value) = filter
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {filter:?}"))?;
+    if key.is_empty() {
+        return Err(format!("missing the prop name in {filter:?}"));
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TimeoutActionArg {
+    Partial,
+    Error,
+}
+
+impl From<TimeoutActionArg> for TimeoutAction {
+    fn from(value: TimeoutActionArg) -> Self {
+        match value {
+            TimeoutActionArg::Partial => TimeoutAction::Partial,
+            TimeoutActionArg::Error => TimeoutAction::Error,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DirectionArg {
+    Sink,
+    Source,
+}
+
+impl From<DirectionArg> for Direction {
+    fn from(value: DirectionArg) -> Self {
+        match value {
+            DirectionArg::Sink => Direction::Input,
+            DirectionArg::Source => Direction::Output,
+        }
+    }
+}
+
+/// Why the command failed, and the exit status to report it with.
+struct Failure {
+    message: String,
+    code: u8,
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self { message, code: 1 }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report which of the allowed clock rates the default sink can run at.
+    Rates,
+    /// Print the rates every sink can run at, i.e. the ones that can be forced
+    /// without resampling anywhere.
+    CommonRates {
+        /// Intersect the rates of the sources instead.
+        #[arg(long)]
+        sources: bool,
+    },
+    /// Print only the rate the graph runs at, the forced one if set.
+    Rate,
+    /// Print only the quantum the graph runs with, the forced one if set.
+    Quantum,
+    /// List the links between ports as `node:port -> node:port`.
+    Links,
+    /// Print the nodes and the links between them as a graph.
+    Graph {
+        #[arg(long, default_value = "dot")]
+        format: GraphFormat,
+    },
+    /// List the devices clustered by their node.group.
+    Groups,
+    /// List the factories the server can create objects with.
+    Factories,
+    /// Dump every prop and param of one node, device or metadata object, given by id
+    /// or by its node, device or metadata name.
+    Inspect { target: String },
+    /// Create a virtual sink and print its node id.
+    CreateNullSink {
+        #[arg(long)]
+        name: String,
+        #[arg(long, default_value_t = 2)]
+        channels: u32,
+    },
+    /// Suspend a device, given by id or node name. It starts again as soon as a stream
+    /// is linked to it.
+    Disable {
+        device: String,
+        /// Switch the whole card of the device off instead, so that apps cannot use it
+        /// until `enable`. Every other device of the card goes with it.
+        #[arg(long)]
+        card: bool,
+    },
+    /// Bring back a device, given by node name, that `disable --card` switched off, by
+    /// putting its card back on the profile it was on.
+    Enable { device: String },
+    /// List the profiles of every card that can be selected right now.
+    Profiles {
+        /// Also list the profiles the card reports as unavailable.
+        #[arg(long)]
+        all_profiles: bool,
+    },
+    /// Remove the links from OUTPUT to INPUT, each a node name or `node:port`.
+    Unlink {
+        #[arg(required_unless_present = "id", conflicts_with = "id")]
+        output: Option<String>,
+        #[arg(required_unless_present = "id")]
+        input: Option<String>,
+        /// Remove the link with this id instead.
+        #[arg(long)]
+        id: Option<u32>,
+    },
+    /// Force a clock rate, check that the graph switched to it and restore the previous setting.
+    Probe {
+        #[arg(long)]
+        rate: u32,
+        /// Refuse to force the rate if a device advertises rates that do not include it.
+        #[arg(long)]
+        strict_rates: bool,
+    },
+    /// Show the peak and RMS levels of a device, given by id or node name.
+    Meter {
+        device: String,
+        /// How long to measure, in seconds.
+        #[arg(long, default_value_t = 3.0)]
+        duration: f64,
+    },
+    /// Record a device, given by id or node name, to a 16 bit WAV file.
+    Record {
+        device: String,
+        /// How long to record.
+        #[arg(long, default_value_t = 3.0)]
+        seconds: f64,
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+    },
+    /// Change the default sink or source, given by node name.
+    Default {
+        #[arg(long, required_unless_present = "source")]
+        sink: Option<String>,
+        #[arg(long)]
+        source: Option<String>,
+        /// Leave defaults that already point at the device alone, printing `unchanged`.
+        #[arg(long)]
+        only_changed: bool,
+    },
+    /// Time a number of snapshots and report the fastest, median and slowest.
+    #[command(hide = true)]
+    Benchmark {
+        #[arg(long, default_value_t = 10)]
+        runs: usize,
+        /// Take every snapshot over one connection instead of one connection each.
+        #[arg(long)]
+        session: bool,
+        /// Sync after every bind instead of once per wave of binds, to time how much
+        /// the batching saves.
+        #[arg(long)]
+        sync_each_bind: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the clock settings and the device volumes, redrawn in place until `q` is pressed.
+    Top {
+        /// Seconds between refreshes.
+        #[arg(long, default_value_t = 1.0)]
+        interval: f64,
+    },
+    /// Wait until a clock setting has a value, e.g. after changing the rate.
+    WaitSettle {
+        /// The settings metadata key, such as `clock.rate`.
+        #[arg(long)]
+        key: String,
+        #[arg(long)]
+        value: String,
+        /// How long to wait, in seconds.
+        #[arg(long, default_value_t = 3.0)]
+        timeout: f64,
+    },
+    /// Save the forced rate and quantum and the default sink and source to a file.
+    SaveProfile { file: PathBuf },
+    /// Re-apply a saved profile, skipping entries the server no longer accepts.
+    LoadProfile {
+        file: PathBuf,
+        /// Skip entries that already hold the saved value, printing `unchanged` if
+        /// none is left.
+        #[arg(long)]
+        only_changed: bool,
+    },
+}
+
+fn save_profile(result: &InitResult, file: &Path) -> Result<(), String> {
+    let profile = Profile::capture(result);
+    let json = serde_json::to_string_pretty(&profile).map_err(|err| err.to_string())?;
+    fs::write(file, json + "\n").map_err(|err| format!("failed to write {}: {err}", file.display()))
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct Device {
-    id: u32,
-    node_name: String,
-    nick_name: String,
-    description: String,
-    direction: Direction,
-    channels: usize,
-    limit_quantum: u32,
-    rate: u32,
-    allow_rates: Vec<u32>,
-    quantum: u32,
-    min_quantum: u32,
-    max_quantum: u32,
+fn load_baseline(file: &Path) -> Result<DriftMonitor, String> {
+    let json = fs::read_to_string(file)
+        .map_err(|err| format!("failed to read {}: {err}", file.display()))?;
+    let baseline: Baseline = serde_json::from_str(&json)
+        .map_err(|err| format!("invalid snapshot {}: {err}", file.display()))?;
+    Ok(DriftMonitor::new(baseline))
 }
 
-impl Device {
-    pub fn id(&self) -> u32 {
-        self.id
+fn load_profile(file: &Path, only_changed: bool, assume_yes: bool) -> Result<(), String> {
+    let json = fs::read_to_string(file)
+        .map_err(|err| format!("failed to read {}: {err}", file.display()))?;
+    let profile: Profile = serde_json::from_str(&json)
+        .map_err(|err| format!("invalid profile {}: {err}", file.display()))?;
+    ask(&format!("apply the profile {}", file.display()), assume_yes)?;
+    let applied = apply_profile(&profile, only_changed, |warning| {
+        eprintln!("warning: {warning}")
+    })
+    .map_err(|err| err.to_string())?;
+    if only_changed && applied == 0 {
+        println!("unchanged");
+        return Ok(());
     }
-    pub fn name(&self) -> &str {
-        &self.nick_name
+    println!("applied {applied} settings from {}", file.display());
+    Ok(())
+}
+
+/// Point the defaults at the named devices, after checking that they exist, then
+/// take a fresh snapshot to confirm the server switched. With `only_changed`,
+/// defaults that are already right are neither written nor confirmed.
+fn change_defaults(
+    result: &InitResult,
+    sink: Option<&str>,
+    source: Option<&str>,
+    only_changed: bool,
+    opts: &InitOptions,
+    assume_yes: bool,
+) -> Result<(), String> {
+    let unchanged = |name: Option<&str>, current: Option<&str>, direction: Direction| {
+        let unchanged = only_changed && name.is_some() && name == current;
+        if unchanged {
+            println!(
+                "default {}: {} (unchanged)",
+                direction.as_str(),
+                name.unwrap_or_default()
+            );
+        }
+        unchanged
+    };
+    let sink = sink.filter(|_| !unchanged(sink, result.default_sink_name(), Direction::Input));
+    let source =
+        source.filter(|_| !unchanged(source, result.default_source_name(), Direction::Output));
+    if sink.is_none() && source.is_none() {
+        return Ok(());
     }
-    pub fn channels(&self) -> usize {
-        self.channels
+    let changes = [(sink, Direction::Input), (source, Direction::Output)];
+    for (name, direction) in changes {
+        let Some(name) = name else {
+            continue;
+        };
+        let exists = result
+            .devices()
+            .iter()
+            .any(|device| device.node_name() == name && device.direction() == direction);
+        if !exists {
+            return Err(format!("no {direction} named {name}"));
+        }
     }
-    pub fn direction(&self) -> Direction {
-        self.direction
+    let action: Vec<String> = changes
+        .iter()
+        .filter_map(|(name, direction)| {
+            name.map(|name| format!("set the default {} to {name}", direction.as_str()))
+        })
+        .collect();
+    ask(&action.join(" and "), assume_yes)?;
+    for (name, direction) in changes {
+        if let Some(name) = name {
+            set_default(direction, name).map_err(|err| err.to_string())?;
+        }
     }
-    pub fn node_name(&self) -> &str {
-        &self.node_name
+    let result = init_roundtrip_with(opts).map_err(|err| err.to_string())?;
+    let current = [
+        (sink, result.default_sink_name(), Direction::Input),
+        (source, result.default_source_name(), Direction::Output),
+    ];
+    for (wanted, current, direction) in current {
+        if wanted.is_none() {
+            continue;
+        }
+        let kind = direction.as_str();
+        if current != wanted {
+            return Err(format!(
+                "the default {kind} is still {}",
+                current.unwrap_or("unset")
+            ));
+        }
+        println!("default {kind}: {}", current.unwrap_or_default());
     }
-    pub fn description(&self) -> &str {
-        &self.description
+    Ok(())
+}
+
+fn print_rates(result: &InitResult) -> Result<(), String> {
+    let sink = result
+        .default_sink()
+        .ok_or_else(|| "no default sink found".to_owned())?;
+    println!(
+        "default sink: {} ({})",
+        sink.description(),
+        sink.node_name()
+    );
+    for rate in result.settings().allow_rates() {
+        let status = match sink.supports_rate(*rate) {
+            Some(true) => "supported",
+            Some(false) => "not supported",
+            None => "unknown",
+        };
+        println!("{rate:>7} {status}");
     }
-    pub fn limit_quantam(&self) -> u32 {
-        self.limit_quantum
+    Ok(())
+}
+
+/// Show a numbered list on stderr and read the chosen entry from stdin, so that
+/// stdout only carries the result.
+fn select_device(devices: &[Device]) -> Result<&Device, String> {
+    if !io::stdin().is_terminal() {
+        return Err("--select needs an interactive terminal on stdin".to_owned());
+    }
+    if devices.is_empty() {
+        return Err("no devices to select from".to_owned());
     }
-    pub fn min_quantum(&self) -> u32 {
-        self.min_quantum
+    let mut stderr = io::stderr();
+    for (index, device) in devices.iter().enumerate() {
+        let _ = writeln!(
+            stderr,
+            "{:>3}) {} ({})",
+            index + 1,
+            device.description(),
+            device.node_name()
+        );
     }
-    pub fn max_quantum(&self) -> u32 {
-        self.max_quantum
+    let _ = write!(stderr, "select [1-{}]: ", devices.len());
+    let _ = stderr.flush();
+
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|err| format!("failed to read the selection: {err}"))?;
+    line.trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|choice| choice.checked_sub(1))
+        .and_then(|index| devices.get(index))
+        .ok_or_else(|| format!("invalid selection: {:?}", line.trim()))
+}
+
+/// Ask on stderr whether to `action`, unless `assume_yes` is set. Anything but `y` or
+/// `yes` is an error, and so is a stdin that is not a terminal: with nobody there to
+/// answer, waiting for one would hang a script.
+fn ask(action: &str, assume_yes: bool) -> Result<(), String> {
+    if assume_yes {
+        return Ok(());
     }
-    pub fn quantum(&self) -> u32 {
-        self.quantum
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "not going to {action} without asking, pass --assume-yes to skip the question"
+        ));
     }
-    pub fn rate(&self) -> u32 {
-        self.rate
+    let mut stderr = io::stderr();
+    let _ = write!(stderr, "{action}? [y/N] ");
+    let _ = stderr.flush();
+
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|err| format!("failed to read the answer: {err}"))?;
+    match line.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err("aborted".to_owned()),
     }
-    pub fn allow_rates(&self) -> &[u32] {
-        &self.allow_rates
+}
+
+/// Take snapshots until `done` holds for one, giving up after [`CONFIRM_ATTEMPTS`].
+fn confirm(done: impl Fn(&InitResult) -> bool) -> Result<bool, String> {
+    for _ in 0..CONFIRM_ATTEMPTS {
+        let result = init_roundtrip_with(&InitOptions::default()).map_err(|err| err.to_string())?;
+        if done(&result) {
+            return Ok(true);
+        }
+        thread::sleep(CONFIRM_INTERVAL);
     }
+    Ok(false)
 }
 
-#[derive(Debug, Clone, Default)]
-struct Settings {
-    rate: u32,
-    allow_rates: Vec<u32>,
-    quantum: u32,
-    min_quantum: u32,
-    max_quantum: u32,
+/// The device `target` names, by id or node name. A node name several devices share
+/// is refused rather than guessed at, since picking the wrong one cannot be undone.
+fn find_device<'a>(devices: &'a [Device], target: &str) -> Result<&'a Device, String> {
+    if let Some(device) = devices
+        .iter()
+        .find(|candidate| candidate.id().to_string() == target)
+    {
+        return Ok(device);
+    }
+    let mut named = devices
+        .iter()
+        .filter(|candidate| candidate.node_name() == target);
+    let device = named.next().ok_or_else(|| format!("no device {target}"))?;
+    if named.next().is_some() {
+        return Err(format!(
+            "several devices are named {target}, give the id instead"
+        ));
+    }
+    Ok(device)
 }
 
-#[allow(dead_code)]
-enum Request {
-    Node(NodeListener),
-    Meta(MetadataListener),
+/// Suspend the device `target` names, or with `card`, switch its whole card off and
+/// remember the profile it was on for `enable`.
+fn disable(target: &str, card: bool, assume_yes: bool) -> Result<(), String> {
+    let result = init_roundtrip_with(&InitOptions::default()).map_err(|err| err.to_string())?;
+    let device = find_device(result.devices(), target)?;
+    let node_name = device.node_name().to_owned();
+    if card {
+        return disable_card(device, assume_yes);
+    }
+    let id = device.id();
+    ask(&format!("suspend {node_name}"), assume_yes)?;
+    if !suspend_node(id).map_err(|err| err.to_string())? {
+        return Err(format!("{node_name} went away"));
+    }
+    let suspended = confirm(|result| {
+        result
+            .devices()
+            .iter()
+            .find(|device| device.id() == id)
+            .is_none_or(|device| device.running_rate().is_none())
+    })?;
+    if !suspended {
+        return Err(format!(
+            "{node_name} is running again, a stream keeps it open; \
+             pass --card to switch its card off instead"
+        ));
+    }
+    println!("suspended {node_name}, it starts again once a stream uses it");
+    Ok(())
 }
 
-impl From<NodeListener> for Request {
-    fn from(value: NodeListener) -> Self {
-        Self::Node(value)
+fn disable_card(device: &Device, assume_yes: bool) -> Result<(), String> {
+    let node_name = device.node_name().to_owned();
+    let card = device
+        .card()
+        .ok_or_else(|| format!("{node_name} has no card that could be switched off"))?;
+    let cards = list_cards().map_err(|err| err.to_string())?;
+    let card = cards
+        .iter()
+        .find(|candidate| candidate.id() == card)
+        .ok_or_else(|| format!("card {card} went away"))?;
+    let off = card
+        .profiles()
+        .iter()
+        .find(|profile| profile.name() == "off")
+        .ok_or_else(|| format!("{} has no off profile", card.name()))?;
+    let previous = card.active_profile().ok_or_else(|| {
+        format!(
+            "cannot tell which profile {} is on, so it could not be restored",
+            card.name()
+        )
+    })?;
+    ask(
+        &format!("switch off {} and with it {node_name}", card.name()),
+        assume_yes,
+    )?;
+    let mut disabled = disabled::load()?;
+    disabled.retain(|known| known.node_name != node_name);
+    disabled.push(DisabledCard {
+        node_name: node_name.clone(),
+        card_name: card.name().to_owned(),
+        profile: previous.name().to_owned(),
+    });
+    disabled::save(&disabled)?;
+    if !set_card_profile(card.id(), off.index()).map_err(|err| err.to_string())? {
+        return Err(format!("{} went away", card.name()));
     }
+    let gone = confirm(|result| {
+        result
+            .devices()
+            .iter()
+            .all(|device| device.node_name() != node_name)
+    })?;
+    if !gone {
+        return Err(format!(
+            "{node_name} is still there after switching {} off",
+            card.name()
+        ));
+    }
+    println!("disabled {node_name}, run `enable {node_name}` to bring it back");
+    Ok(())
 }
 
-impl From<MetadataListener> for Request {
-    fn from(value: MetadataListener) -> Self {
-        Self::Meta(value)
+/// Undo `disable`: put the card of a device disabled with `--card` back on the
+/// profile it was on. A suspended device needs nothing, the graph starts it again.
+fn enable(target: &str, assume_yes: bool) -> Result<(), String> {
+    let mut disabled = disabled::load()?;
+    let Some(index) = disabled.iter().position(|known| known.node_name == target) else {
+        let result = init_roundtrip_with(&InitOptions::default()).map_err(|err| err.to_string())?;
+        let device = find_device(result.devices(), target)?;
+        println!(
+            "{} is not disabled, a suspended device starts again once a stream uses it",
+            device.node_name()
+        );
+        return Ok(());
+    };
+    let entry = disabled[index].clone();
+    let cards = list_cards().map_err(|err| err.to_string())?;
+    let card = cards
+        .iter()
+        .find(|candidate| candidate.name() == entry.card_name)
+        .ok_or_else(|| format!("no card {}", entry.card_name))?;
+    let profile = card
+        .profiles()
+        .iter()
+        .find(|profile| profile.name() == entry.profile)
+        .ok_or_else(|| format!("{} no longer has profile {}", card.name(), entry.profile))?;
+    ask(
+        &format!("switch {} back to profile {}", card.name(), profile.name()),
+        assume_yes,
+    )?;
+    if !set_card_profile(card.id(), profile.index()).map_err(|err| err.to_string())? {
+        return Err(format!("{} went away", card.name()));
+    }
+    let back = confirm(|result| {
+        result
+            .devices()
+            .iter()
+            .any(|device| device.node_name() == entry.node_name)
+    })?;
+    if !back {
+        return Err(format!(
+            "{target} did not come back after switching {} to {}",
+            card.name(),
+            profile.name()
+        ));
     }
+    disabled.remove(index);
+    disabled::save(&disabled)?;
+    println!(
+        "enabled {target}, {} is on profile {}",
+        card.name(),
+        profile.name()
+    );
+    Ok(())
 }
 
-fn init_roundtrip() -> Option<Vec<Device>> {
-    let mainloop = pw::main_loop::MainLoopRc::new(None).ok()?;
-    let context = pw::context::ContextRc::new(&mainloop, None).ok()?;
-    let core = context.connect_rc(None).ok()?;
-    let registry = core.get_registry_rc().ok()?;
+fn print_version() {
+    println!("pwtrain {}", env!("CARGO_PKG_VERSION"));
+    println!("libpipewire {}", library_version());
+    match server_version() {
+        Ok(version) => println!("server {version}"),
+        Err(err) => println!("server unavailable ({err})"),
+    }
+}
 
-    // To comply with Rust's safety rules, we wrap this variable in an `Rc` and  a `Cell`.
-    let devices: Rc<RefCell<Vec<Device>>> = Rc::new(RefCell::new(vec![]));
-    let requests = Rc::new(RefCell::new(vec![]));
-    let settings = Rc::new(RefCell::new(Settings::default()));
-    let loop_clone = mainloop.clone();
+fn format_levels(levels: &[Level]) -> String {
+    let levels: Vec<String> = levels
+        .iter()
+        .map(|level| {
+            format!(
+                "peak {:6.1} dBFS rms {:6.1} dBFS",
+                level.peak_db(),
+                level.rms_db()
+            )
+        })
+        .collect();
+    levels.join(" | ")
+}
 
-    // Trigger the sync event. The server's answer won't be processed until we start the main loop,
-    // so we can safely do this before setting up a callback. This lets us avoid using a Cell.
-    let peddings: Rc<RefCell<Vec<AsyncSeq>>> = Rc::new(RefCell::new(vec![]));
-    let pending = core.sync(0).expect("sync failed");
+fn print_groups(devices: &[Device]) {
+    let mut groups: BTreeMap<Option<&str>, Vec<&Device>> = BTreeMap::new();
+    for device in devices {
+        groups.entry(device.group()).or_default().push(device);
+    }
+    // Ungrouped devices go last rather than first.
+    let ungrouped = groups.remove(&None).map(|members| (None, members));
+    for (group, members) in groups.into_iter().chain(ungrouped) {
+        println!("{}", group.unwrap_or("(no group)"));
+        for device in members {
+            println!("  {} {}", device.id(), device.node_name());
+        }
+    }
+}
 
-    peddings.borrow_mut().push(pending);
+fn print_profiles(cards: &[Card], all_profiles: bool) {
+    for card in cards {
+        println!("{} ({})", card.description(), card.name());
+        let profiles = card
+            .profiles()
+            .iter()
+            .filter(|profile| all_profiles || profile.available() != Availability::No);
+        for profile in profiles {
+            let status = match profile.available() {
+                Availability::Yes => "",
+                Availability::No => " [unavailable]",
+                Availability::Unknown => " [availability unknown]",
+            };
+            let active = if card.active_profile() == Some(profile) {
+                " [active]"
+            } else {
+                ""
+            };
+            println!(
+                "  {:>3} {} ({}){status}{active}",
+                profile.index(),
+                profile.name(),
+                profile.description()
+            );
+        }
+    }
+}
 
-    let _listener_core = core
-        .add_listener_local()
-        .done({
-            let peddings = peddings.clone();
-            move |id, seq| {
-                if id != pw::core::PW_ID_CORE {
-                    return;
+fn run(cli: &Cli) -> Result<(), Failure> {
+    cli.check()?;
+    set_remote(cli.remote.as_deref());
+    if cli.version {
+        print_version();
+        return Ok(());
+    }
+    if cli.watch || cli.watch_settings_only {
+        let opts = WatchOptions {
+            handle_signals: true,
+            settings_only: cli.watch_settings_only,
+            reconnect_attempts: cli.max_reconnects,
+            snapshot_on_sigusr1: true,
+            ..Default::default()
+        };
+        let stop = opts.stop.clone();
+        let mut history = RateHistory::new(cli.log_file.as_deref())
+            .map_err(|err| format!("failed to open the log file: {err}"))?;
+        let mut drift = cli.since.as_deref().map(load_baseline).transpose()?;
+        let settings_only = cli.watch_settings_only;
+        let mut clock = None;
+        let mut hook = cli.on_change.clone().map(ChangeHook::new);
+        let recent = cli
+            .recent
+            .map(|capacity| Rc::new(RefCell::new(RecentEvents::new(capacity))));
+        let control = cli
+            .control_socket
+            .is_some()
+            .then(|| Arc::new(Mutex::new(ControlState::default())));
+        let _socket = match (&cli.control_socket, &control) {
+            (Some(path), Some(control)) => Some(
+                serve(path, control.clone(), cli.assume_yes)
+                    .map_err(|err| format!("failed to listen on {}: {err}", path.display()))?,
+            ),
+            _ => None,
+        };
+        let mut event_filter = cli
+            .watch_filter
+            .then(|| EventFilter::new(device_filter(cli)));
+        let result = watch(opts, {
+            let recent = recent.clone();
+            move |event| {
+                if let Some(recent) = &recent {
+                    recent.borrow_mut().push(&event);
                 }
-                let mut peddinglist = peddings.borrow_mut();
-                let Some(index) = peddinglist.iter().position(|o_seq| *o_seq == seq) else {
-                    return;
+                if let Some(control) = &control {
+                    control
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .record(&event);
+                }
+                let event = match &mut event_filter {
+                    Some(event_filter) => match event_filter.apply(event) {
+                        Some(event) => event,
+                        None => return,
+                    },
+                    None => event,
                 };
-                peddinglist.remove(index);
-                if !peddinglist.is_empty() {
-                    return;
+                let change = history.record(&event);
+                if let Some(warning) = hook.as_mut().and_then(|hook| hook.run(&event)) {
+                    eprintln!("warning: {warning}");
                 }
-                loop_clone.quit();
-            }
-        })
-        .register();
-    let _listener_reg = registry
-        .add_listener_local()
-        .global({
-            let devices = devices.clone();
-            let registry = registry.clone();
-            let requests = requests.clone();
-            let settings = settings.clone();
-            move |global| match global.type_ {
-                pipewire::types::ObjectType::Metadata => {
-                    if !global.props.is_some_and(|props| {
-                        props
-                            .get("metadata.name")
-                            .is_some_and(|name| name == "settings")
-                    }) {
+                if settings_only {
+                    let PwEvent::SettingsChanged(settings) = event else {
                         return;
+                    };
+                    let current = (
+                        settings.rate(),
+                        settings.quantum(),
+                        settings.allow_rates().to_vec(),
+                    );
+                    if clock.as_ref() != Some(&current) {
+                        let (rate, quantum, allow_rates) = &current;
+                        println!(
+                            "clock.rate {rate} clock.quantum {quantum} allowed-rates {allow_rates:?}"
+                        );
+                        clock = Some(current);
                     }
-                    let meta_settings: Metadata = registry.bind(global).unwrap();
-                    let settings = settings.clone();
-                    let listener = meta_settings
-                        .add_listener_local()
-                        .property(move |_, key, _, value| {
-                            match (key, value) {
-                                (Some("clock.rate"), Some(rate)) => {
-                                    let Ok(rate) = rate.parse() else {
-                                        return 0;
-                                    };
-                                    settings.borrow_mut().rate = rate;
-                                }
-                                (Some("clock.allowed-rates"), Some(list)) => {
-                                    let Some(list) = list.strip_prefix("[") else {
-                                        return 0;
-                                    };
-                                    let Some(list) = list.strip_suffix("]") else {
-                                        return 0;
-                                    };
-                                    let list = list.trim();
-                                    let list: Vec<&str> = list.split(' ').collect();
-                                    let mut allow_rates = vec![];
-                                    for rate in list {
-                                        let Ok(rate) = rate.parse() else {
-                                            return 0;
-                                        };
-                                        allow_rates.push(rate);
-                                    }
-                                    settings.borrow_mut().allow_rates = allow_rates;
-                                }
-                                (Some("clock.quantum"), Some(quantum)) => {
-                                    let Ok(quantum) = quantum.parse() else {
-                                        return 0;
-                                    };
-                                    settings.borrow_mut().quantum = quantum;
-                                }
-                                (Some("clock.min-quantum"), Some(min_quantum)) => {
-                                    let Ok(min_quantum) = min_quantum.parse() else {
-                                        return 0;
-                                    };
-                                    settings.borrow_mut().min_quantum = min_quantum;
-                                }
-                                (Some("clock.max-quantum"), Some(max_quantum)) => {
-                                    let Ok(max_quantum) = max_quantum.parse() else {
-                                        return 0;
-                                    };
-                                    settings.borrow_mut().max_quantum = max_quantum;
-                                }
-                                _ => {}
-                            }
-                            0
-                        })
-                        .register();
-                    let pending = core.sync(0).expect("sync failed");
-                    peddings.borrow_mut().push(pending);
-                    requests
-                        .borrow_mut()
-                        .push((meta_settings.upcast(), Request::Meta(listener)));
+                    return;
                 }
-                pipewire::types::ObjectType::Node => {
-                    let Some(props) = global.props else {
-                        return;
-                    };
-                    let Some(media_class) = props.get("media.class") else {
-                        return;
-                    };
-                    match media_class {
-                        "Audio/Sink" => Direction::Input,
-                        "Audio/Source" => Direction::Output,
-                        _ => {
-                            return;
-                        }
-                    };
-                    let node: Node = registry.bind(global).expect("should ok");
-
-                    let devices = devices.clone();
-                    let listener = node
-                        .add_listener_local()
-                        .info(move |info| {
-                            dbg!(info);
-                            let Some(props) = info.props() else {
-                                return;
-                            };
-                            let Some(media_class) = props.get("media.class") else {
-                                return;
-                            };
-                            let direction = match media_class {
-                                "Audio/Sink" => Direction::Input,
-                                "Audio/Source" => Direction::Output,
-                                _ => {
-                                    return;
-                                }
-                            };
-                            let id = info.id();
-                            let node_name = props.get("node.name").unwrap_or("unknown").to_owned();
-                            let nick_name = props.get("node.nick").unwrap_or("unknown").to_owned();
-                            let description = props
-                                .get("node.description")
-                                .unwrap_or("unknown")
-                                .to_owned();
-                            let channels: usize = props
-                                .get("audio.channels")
-                                .and_then(|channels| channels.parse().ok())
-                                .unwrap_or(2);
-                            let limit_quantum: u32 = props
-                                .get("clock.quantum-limit")
-                                .and_then(|channels| channels.parse().ok())
-                                .unwrap_or(0);
-                            let device = Device {
-                                id,
-                                node_name,
-                                nick_name,
-                                description,
-                                direction,
-                                channels,
-                                limit_quantum,
-                                ..Default::default()
-                            };
-                            devices.borrow_mut().push(device);
-                        })
-                        .register();
-                    let pending = core.sync(0).expect("sync failed");
-                    peddings.borrow_mut().push(pending);
-                    requests
-                        .borrow_mut()
-                        .push((node.upcast(), Request::Node(listener)));
+                if let Some(drift) = &mut drift {
+                    if let Some(report) = drift.record(&event) {
+                        println!("{report}");
+                    }
+                    return;
+                }
+                println!("{event:?}");
+                if let Some(change) = change {
+                    println!("{change}");
                 }
-                _ => {}
             }
         })
-        .register();
+        .map_err(|err| err.to_string());
+        if stop.is_stopped() && !cli.is_quiet() {
+            eprintln!("stopping");
+        }
+        if let Some(recent) = recent {
+            for (time, event) in recent.borrow().iter() {
+                let ms = time
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_millis())
+                    .unwrap_or_default();
+                eprintln!("{ms} {event:?}");
+            }
+        }
+        let _ = io::stdout().flush();
+        return Ok(result?);
+    }
+    if let Some(Command::Rate | Command::Quantum) = cli.command {
+        let settings = read_settings().map_err(|err| err.to_string())?;
+        match cli.command {
+            Some(Command::Rate) => println!("{}", settings.effective_rate()),
+            _ => println!("{}", settings.effective_quantum()),
+        }
+        return Ok(());
+    }
+    if let Some(Command::Probe { rate, strict_rates }) = cli.command {
+        if strict_rates {
+            let opts = InitOptions {
+                class: cli.class.clone(),
+                ..Default::default()
+            };
+            let result = init_roundtrip_with(&opts).map_err(|err| err.to_string())?;
+            let incompatible: Vec<&str> = result
+                .devices_without_rate(rate)
+                .into_iter()
+                .map(Device::node_name)
+                .collect();
+            if !incompatible.is_empty() {
+                return Err(format!(
+                    "{rate}: not supported by {}, nothing was changed",
+                    incompatible.join(", ")
+                )
+                .into());
+            }
+        }
+        ask(
+            &format!("force the graph to {rate} Hz for a moment"),
+            cli.assume_yes,
+        )?;
+        let probe = probe_rate(rate).map_err(|err| err.to_string())?;
+        if probe.succeeded() {
+            println!("{rate}: ok");
+            return Ok(());
+        }
+        return Err(format!("{rate}: failed, the graph ran at {}", probe.achieved()).into());
+    }
+    if let Some(Command::WaitSettle {
+        key,
+        value,
+        timeout,
+    }) = &cli.command
+    {
+        if Settings::default().has_value(key, value).is_none() {
+            return Err(
+                format!("cannot wait for {key}: not a clock setting or not a valid value").into(),
+            );
+        }
+        let timeout = Duration::try_from_secs_f64(*timeout)
+            .map_err(|err| format!("invalid --timeout: {err}"))?;
+        if !wait_settle(key, value, timeout).map_err(|err| err.to_string())? {
+            return Err(format!("timed out waiting for {key} to become {value}").into());
+        }
+        if !cli.is_quiet() {
+            println!("{key} is {value}");
+        }
+        return Ok(());
+    }
+    if let Some(Command::Inspect { target }) = &cli.command {
+        let inspection = inspect(target).map_err(|err| err.to_string())?;
+        let inspection =
+            inspection.ok_or_else(|| format!("no node, device or metadata {target}"))?;
+        print!("{inspection}");
+        return Ok(());
+    }
+    if let Some(Command::Factories) = cli.command {
+        let factories = list_factories().map_err(|err| err.to_string())?;
+        for factory in factories {
+            println!(
+                "{:>5} {} ({})",
+                factory.id(),
+                factory.name(),
+                factory.type_name()
+            );
+        }
+        return Ok(());
+    }
+    if let Some(Command::CreateNullSink { name, channels }) = &cli.command {
+        let factories = list_factories().map_err(|err| err.to_string())?;
+        if !factories.iter().any(|factory| factory.name() == "adapter") {
+            return Err("the server has no adapter factory".to_owned().into());
+        }
+        ask(&format!("create the null sink {name}"), cli.assume_yes)?;
+        let id = create_null_sink(name, *channels)
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("created {name}, but it did not show up as a sink"))?;
+        println!("{id}");
+        return Ok(());
+    }
+    if let Some(Command::Profiles { all_profiles }) = cli.command {
+        let cards = list_cards().map_err(|err| err.to_string())?;
+        print_profiles(&cards, all_profiles);
+        return Ok(());
+    }
+    if let Some(Command::Disable { device, card }) = &cli.command {
+        return Ok(disable(device, *card, cli.assume_yes)?);
+    }
+    if let Some(Command::Enable { device }) = &cli.command {
+        return Ok(enable(device, cli.assume_yes)?);
+    }
+    if let Some(Command::LoadProfile { file, only_changed }) = &cli.command {
+        return Ok(load_profile(file, *only_changed, cli.assume_yes)?);
+    }
+    let opts = InitOptions {
+        class: cli.class.clone(),
+        tag: cli.tag.clone(),
+        max_devices: cli.max_devices,
+        timeout: cli
+            .timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|err| format!("invalid --timeout: {err}"))?,
+        timeout_action: cli.timeout_action.into(),
+        route_settings: cli.route_settings,
+        node_version: cli.node_version,
+        ..Default::default()
+    };
+    if let Some(Command::Benchmark {
+        runs,
+        session,
+        sync_each_bind,
+        json,
+    }) = cli.command
+    {
+        let opts = InitOptions {
+            sync_each_bind,
+            ..opts
+        };
+        let report = benchmark(&opts, runs, session)?;
+        if json {
+            let json = serde_json::to_string_pretty(&report).map_err(|err| err.to_string())?;
+            println!("{json}");
+        } else {
+            print!("{report}");
+        }
+        return Ok(());
+    }
+    if let Some(Command::Top { interval }) = cli.command {
+        let interval = Duration::try_from_secs_f64(interval)
+            .map_err(|err| format!("invalid --interval: {err}"))?;
+        return Ok(top(&opts, interval)?);
+    }
+    if let Some(interval) = cli.watch_interval {
+        let interval = Duration::try_from_secs_f64(interval)
+            .map_err(|err| format!("invalid --watch-interval: {err}"))?;
+        let session = Session::new().map_err(|err| err.to_string())?;
+        loop {
+            let result = session.snapshot(&opts).map_err(|err| err.to_string())?;
+            report(cli, result)?;
+            let _ = io::stdout().flush();
+            thread::sleep(interval);
+        }
+    }
+    if let Some(timeout) = cli.poll_until_stable {
+        let timeout = Duration::try_from_secs_f64(timeout)
+            .map_err(|err| format!("invalid --poll-until-stable: {err}"))?;
+        let session = Session::new().map_err(|err| err.to_string())?;
+        let (result, stable) = session
+            .snapshot_until_stable(&opts, STABLE_INTERVAL, timeout)
+            .map_err(|err| err.to_string())?;
+        if !stable {
+            eprintln!("warning: devices were still changing after {timeout:?}");
+        }
+        return report(cli, result);
+    }
+    let Some(ttl) = cli.cache_ttl else {
+        let result = init_roundtrip_with(&opts).map_err(|err| err.to_string())?;
+        return report(cli, result);
+    };
+    let ttl =
+        Duration::try_from_secs_f64(ttl).map_err(|err| format!("invalid --cache-ttl: {err}"))?;
+    let key = format!(
+        "{:?}",
+        (
+            &opts.class,
+            &opts.tag,
+            opts.max_devices,
+            opts.route_settings,
+            opts.node_version,
+            &cli.remote,
+            std::env::var("PIPEWIRE_REMOTE").ok()
+        )
+    );
+    let cached = (!cli.no_cache).then(|| cache::load(&key, ttl)).flatten();
+    let result = match cached {
+        Some(result) => result,
+        None => {
+            let result = init_roundtrip_with(&opts).map_err(|err| err.to_string())?;
+            if result.complete() {
+                cache::store(&key, &result);
+            }
+            result
+        }
+    };
+    report(cli, result)
+}
 
-    mainloop.run();
+/// Everything that works on a finished snapshot.
+fn report(cli: &Cli, result: InitResult) -> Result<(), Failure> {
+    match &cli.command {
+        Some(Command::Default {
+            sink,
+            source,
+            only_changed,
+        }) => {
+            let opts = InitOptions {
+                class: cli.class.clone(),
+                ..Default::default()
+            };
+            return Ok(change_defaults(
+                &result,
+                sink.as_deref(),
+                source.as_deref(),
+                *only_changed,
+                &opts,
+                cli.assume_yes,
+            )?);
+        }
+        Some(Command::Rates) => return Ok(print_rates(&result)?),
+        Some(Command::CommonRates { sources }) => {
+            let (direction, kind) = if *sources {
+                (Direction::Output, "source")
+            } else {
+                (Direction::Input, "sink")
+            };
+            let rates = result
+                .common_rates(direction)
+                .ok_or_else(|| format!("no {kind} advertises its rates"))?;
+            if rates.is_empty() {
+                return Err(format!("no rate is supported by every {kind}").into());
+            }
+            for rate in rates {
+                println!("{rate}");
+            }
+            return Ok(());
+        }
+        Some(Command::Links) => {
+            for link in result.links() {
+                println!("{link}");
+            }
+            return Ok(());
+        }
+        Some(Command::Graph { format }) => {
+            print!("{}", render_graph(*format, &result));
+            return Ok(());
+        }
+        Some(Command::Meter { device, duration }) => {
+            let duration = Duration::try_from_secs_f64(*duration)
+                .map_err(|err| format!("invalid --duration: {err}"))?;
+            let device = find_device(result.devices(), device)?;
+            let total = meter(device, duration, |levels| {
+                println!("{}", format_levels(levels))
+            })
+            .map_err(|err| err.to_string())?;
+            println!("total {}", format_levels(&total));
+            return Ok(());
+        }
+        Some(Command::Record {
+            device,
+            seconds,
+            out,
+        }) => {
+            let duration = Duration::try_from_secs_f64(*seconds)
+                .map_err(|err| format!("invalid --seconds: {err}"))?;
+            let device = find_device(result.devices(), device)?;
+            let recording = record(device, duration).map_err(|err| err.to_string())?;
+            let file = fs::File::create(out)
+                .map_err(|err| format!("failed to create {}: {err}", out.display()))?;
+            recording
+                .write_wav(io::BufWriter::new(file))
+                .map_err(|err| format!("failed to write {}: {err}", out.display()))?;
+            eprintln!(
+                "recorded {:.1} s, {} channels at {} Hz to {}",
+                recording.duration().as_secs_f64(),
+                recording.channels(),
+                recording.rate(),
+                out.display()
+            );
+            return Ok(());
+        }
+        Some(Command::SaveProfile { file }) => return Ok(save_profile(&result, file)?),
+        Some(Command::Unlink { output, input, id }) => {
+            let ids: Vec<u32> = match (id, output, input) {
+                (Some(id), _, _) => vec![*id],
+                (None, Some(output), Some(input)) => result
+                    .links()
+                    .iter()
+                    .filter(|link| link.connects(output, input))
+                    .map(|link| link.id())
+                    .collect(),
+                _ => vec![],
+            };
+            if ids.is_empty() {
+                return Err("no matching links".to_owned().into());
+            }
+            ask(&format!("destroy {} links", ids.len()), cli.assume_yes)?;
+            let removed = unlink(&ids).map_err(|err| err.to_string())?;
+            println!("destroyed {removed} of {} links", ids.len());
+            return Ok(());
+        }
+        _ => {}
+    }
+    let expected = ExpectedSettings {
+        rate: cli.expect_rate,
+        quantum: cli.expect_quantum,
+    };
+    let mismatches = result.settings().compare(&expected);
+    if !mismatches.is_empty() {
+        let mismatches: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+        return Err(format!("settings mismatch\n  {}", mismatches.join("\n  ")).into());
+    }
+    if cli.verbose {
+        for check in result.metadata_checks() {
+            eprintln!(
+                "metadata {} ({}): {}, {}",
+                check.id(),
+                check.name().unwrap_or("unnamed"),
+                if check.accepted() { "read" } else { "skipped" },
+                check.reason()
+            );
+        }
+        for (id, name) in result.unclassified_nodes() {
+            eprintln!("warning: node {id} ({name}) has no media.class, skipped");
+        }
+        for device in result.devices() {
+            for key in device.missing_props() {
+                eprintln!(
+                    "warning: node {} ({}) has no {key}, using the default",
+                    device.id(),
+                    device.node_name()
+                );
+            }
+        }
+    }
+    if cli.explain {
+        for decision in result.decisions() {
+            eprintln!(
+                "{} {} ({}) {}: {}, {}",
+                decision.id(),
+                decision.kind(),
+                decision.media_class().unwrap_or("-"),
+                decision.permissions().unwrap_or("-"),
+                if decision.kept() { "kept" } else { "skipped" },
+                decision.reason()
+            );
+        }
+    }
+    if !cli.is_quiet() {
+        for (id, err) in result.bind_failures() {
+            eprintln!("warning: skipped object {id}, it could not be bound: {err}");
+        }
+        for (id, version) in result.old_versions() {
+            eprintln!(
+                "warning: object {id} is at interface version {version}, older than asked \
+                 for, some of its params may be missing"
+            );
+        }
+        for id in result.vanished() {
+            eprintln!("warning: skipped node {id}, it vanished during param fetch");
+        }
+    }
+    if !result.complete() && !cli.silent {
+        eprintln!("warning: timed out, the list may be incomplete");
+    }
+    if result.truncated() && !cli.silent {
+        eprintln!(
+            "warning: stopped at {} devices, the list is truncated",
+            result.devices().len()
+        );
+    }
+    let hostname = result.hostname().to_owned();
+    let tag = result.tag().map(str::to_owned);
+    let routes = result.routes().to_vec();
+    let devices = if cli.only_default {
+        [result.default_sink(), result.default_source()]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    } else {
+        result.into_devices()
+    };
+    let filter = device_filter(cli);
+    let mut devices: Vec<Device> = devices
+        .into_iter()
+        .filter(|device| filter.matches(device))
+        .collect();
+    if !cli.is_quiet() {
+        for collision in Device::name_collisions(&devices) {
+            eprintln!(
+                "warning: {} devices are named {} (ids {:?}), select them by id or serial",
+                collision.ids().len(),
+                collision.node_name(),
+                collision.ids()
+            );
+        }
+    }
+    if cli.first {
+        devices.truncate(1);
+    }
+    if (cli.fail_if_empty || cli.first) && devices.is_empty() {
+        return Err(Failure {
+            message: "no devices found".to_owned(),
+            code: 3,
+        });
+    }
+    if cli.select {
+        let device = select_device(&devices)?;
+        eprintln!("selected {}", device.node_name());
+        println!("{}", device.id());
+        return Ok(());
+    }
+    if let Some(Command::Groups) = cli.command {
+        print_groups(&devices);
+        return Ok(());
+    }
+    let config = Config::load(cli.config.as_deref())?;
+    let snapshot = Snapshot {
+        hostname: &hostname,
+        tag: tag.as_deref(),
+        devices: &devices,
+        routes: &routes,
+        fields: &config.fields,
+    };
+    match cli.format {
+        _ if let Some(template) = &cli.output_template => {
+            print!(
+                "{}",
+                Template::parse(template, &config.fields)?.render(&snapshot)?
+            );
+        }
+        Some(format) => {
+            let style = TableStyle {
+                color: use_color(
+                    cli.no_color,
+                    std::env::var_os("NO_COLOR").as_deref(),
+                    io::stdout().is_terminal(),
+                ),
+                group_by: cli.group_by,
+                max_width: cli.max_width,
+                compact_arrays: cli.json_compact_arrays,
+            };
+            print!("{}", render(format, &snapshot, style)?);
+        }
+        None if cli.is_quiet() => {}
+        None => println!("devices {devices:?}"),
+    }
+    if cli.human_stderr {
+        let style = TableStyle {
+            color: use_color(
+                cli.no_color,
+                std::env::var_os("NO_COLOR").as_deref(),
+                io::stderr().is_terminal(),
+            ),
+            group_by: cli.group_by,
+            max_width: cli.max_width,
+            ..Default::default()
+        };
+        eprint!("{}", render(Format::Table, &snapshot, style)?);
+    }
+    Ok(())
+}
+
+/// Keeps pipewire initialised and deinitialises it when dropped, so that every
+/// way out of `main`, unwinding panics included, runs `pw::deinit`.
+struct PwGuard;
+
+impl PwGuard {
+    fn init() -> Self {
+        pw::init();
+        Self
+    }
+}
 
-    let mut devices = devices.take();
-    let settings = settings.take();
-    for device in devices.iter_mut() {
-        device.rate = settings.rate;
-        device.allow_rates = settings.allow_rates.clone();
-        device.quantum = settings.quantum;
-        device.min_quantum = settings.min_quantum;
-        device.max_quantum = settings.max_quantum;
+impl Drop for PwGuard {
+    fn drop(&mut self) {
+        // SAFETY: every pipewire object is created and dropped inside `run`, which has
+        // returned or unwound by the time the guard goes away.
+        unsafe {
+            pw::deinit();
+        }
     }
-    Some(devices)
 }
 
-fn main() {
-    pw::init();
-    let devices = init_roundtrip().unwrap();
-    println!("devices {devices:?}");
-    unsafe {
-        pw::deinit();
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let guard = PwGuard::init();
+    let result = run(&cli);
+    if cli.no_deinit {
+        std::mem::forget(guard);
+    } else {
+        drop(guard);
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if !cli.silent {
+                eprintln!("error: {}", failure.message);
+            }
+            ExitCode::from(failure.code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_ttl_is_rejected_for_unlink() {
+        let cli = Cli::try_parse_from(["pwtrain", "--cache-ttl", "5", "unlink", "a", "b"]).unwrap();
+        assert!(cli.check().is_err());
+        let cli = Cli::try_parse_from(["pwtrain", "--cache-ttl", "5", "links"]).unwrap();
+        assert!(cli.check().is_ok());
+    }
+
+    #[test]
+    fn watch_interval_only_repeats_listings() {
+        for args in [
+            &["unlink", "a", "b"][..],
+            &["default", "--sink", "hdmi"],
+            &["record", "hdmi", "--out", "out.wav"],
+        ] {
+            let cli = Cli::try_parse_from(["pwtrain", "--watch-interval", "1"].iter().chain(args))
+                .unwrap();
+            assert!(cli.check().is_err(), "{args:?}");
+        }
+        let cli = Cli::try_parse_from(["pwtrain", "--watch-interval", "1"]).unwrap();
+        assert!(cli.check().is_ok());
     }
 }
@@ -0,0 +1,201 @@
+use std::{cell::RefCell, io::Cursor, rc::Rc, time::Duration};
+
+use pipewire::{
+    self as pw,
+    properties::properties,
+    spa::{
+        self,
+        param::{
+            ParamType,
+            audio::{AudioFormat, AudioInfoRaw},
+        },
+        pod::{Object, Pod, Value, serialize::PodSerializer},
+        utils::SpaTypes,
+    },
+    stream::{StreamBox, StreamFlags},
+};
+
+use crate::{Device, Direction, PwError, roundtrip::connect};
+
+/// How often [`meter`] reports the levels of the last window.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Peak and RMS of one channel over some window.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Level {
+    peak: f32,
+    sum_squares: f64,
+    samples: u64,
+}
+
+impl Level {
+    /// The largest absolute sample value, 1.0 being full scale.
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+    pub fn rms(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        (self.sum_squares / self.samples as f64).sqrt() as f32
+    }
+    pub fn peak_db(&self) -> f32 {
+        to_db(self.peak())
+    }
+    pub fn rms_db(&self) -> f32 {
+        to_db(self.rms())
+    }
+
+    fn add(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        self.sum_squares += f64::from(sample) * f64::from(sample);
+        self.samples += 1;
+    }
+
+    fn merge(&mut self, other: &Level) {
+        self.peak = self.peak.max(other.peak);
+        self.sum_squares += other.sum_squares;
+        self.samples += other.samples;
+    }
+}
+
+fn to_db(value: f32) -> f32 {
+    20.0 * value.max(1e-10).log10()
+}
+
+#[derive(Debug, Default)]
+struct MeterState {
+    format: AudioInfoRaw,
+    window: Vec<Level>,
+    total: Vec<Level>,
+}
+
+impl MeterState {
+    fn process(&mut self, samples: &[u8]) {
+        let channels = self.format.channels() as usize;
+        if channels == 0 {
+            return;
+        }
+        self.window.resize(channels, Level::default());
+        let samples = samples
+            .chunks_exact(size_of::<f32>())
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        for (index, sample) in samples.enumerate() {
+            self.window[index % channels].add(sample);
+        }
+    }
+
+    /// Fold the current window into the totals and hand it out.
+    fn take_window(&mut self) -> Vec<Level> {
+        let window = std::mem::take(&mut self.window);
+        self.total
+            .resize(window.len().max(self.total.len()), Level::default());
+        for (total, level) in self.total.iter_mut().zip(&window) {
+            total.merge(level);
+        }
+        window
+    }
+}
+
+/// An `EnumFormat` pod asking for raw audio in `format`, leaving the rate and the
+/// channels to the node.
+pub(crate) fn format_param(format: AudioFormat) -> Vec<u8> {
+    let mut audio_info = AudioInfoRaw::new();
+    audio_info.set_format(format);
+    let object = Object {
+        type_: SpaTypes::ObjectParamFormat.as_raw(),
+        id: ParamType::EnumFormat.as_raw(),
+        properties: audio_info.into(),
+    };
+    PodSerializer::serialize(Cursor::new(Vec::new()), &Value::Object(object))
+        .expect("serializing a fixed format pod")
+        .0
+        .into_inner()
+}
+
+/// Capture from `device` for `duration` and measure its per channel levels.
+///
+/// Sinks are metered through their monitor. `on_update` gets the levels of each
+/// quarter second window as they come in; the levels over the whole run are
+/// returned. The capture stream and its links are removed before returning.
+pub fn meter(
+    device: &Device,
+    duration: Duration,
+    on_update: impl FnMut(&[Level]) + 'static,
+) -> Result<Vec<Level>, PwError> {
+    let (mainloop, core, _registry) = connect()?;
+
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Production",
+        *pw::keys::TARGET_OBJECT => device.node_name(),
+    };
+    if device.direction() == Direction::Input {
+        props.insert(*pw::keys::STREAM_CAPTURE_SINK, "true");
+    }
+    let stream = StreamBox::new(&core, "pwtrain-meter", props).map_err(PwError::Stream)?;
+
+    let state = Rc::new(RefCell::new(MeterState::default()));
+    let _listener = stream
+        .add_local_listener_with_user_data(state.clone())
+        .param_changed(|_, state, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != ParamType::Format.as_raw() {
+                return;
+            }
+            let _ = state.borrow_mut().format.parse(param);
+        })
+        .process(|stream, state| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let Some(data) = buffer.datas_mut().first_mut() else {
+                return;
+            };
+            let offset = data.chunk().offset() as usize;
+            let size = data.chunk().size() as usize;
+            if let Some(samples) = data.data() {
+                let start = offset.min(samples.len());
+                let end = (offset + size).min(samples.len());
+                state.borrow_mut().process(&samples[start..end]);
+            }
+        })
+        .register()
+        .map_err(PwError::Stream)?;
+
+    let format = format_param(AudioFormat::F32LE);
+    let mut params = [Pod::from_bytes(&format).expect("a serialized pod")];
+    stream
+        .connect(
+            spa::utils::Direction::Input,
+            None,
+            StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+            &mut params,
+        )
+        .map_err(PwError::Stream)?;
+
+    let update = mainloop.loop_().add_timer({
+        let state = state.clone();
+        let on_update = RefCell::new(on_update);
+        move |_| {
+            let window = state.borrow_mut().take_window();
+            (on_update.borrow_mut())(&window);
+        }
+    });
+    let _ = update.update_timer(Some(UPDATE_INTERVAL), Some(UPDATE_INTERVAL));
+    let stop = mainloop.loop_().add_timer({
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    let _ = stop.update_timer(Some(duration), None);
+
+    mainloop.run();
+
+    let _ = stream.disconnect();
+    let mut state = state.borrow_mut();
+    state.take_window();
+    Ok(std::mem::take(&mut state.total))
+}
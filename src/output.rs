@@ -0,0 +1,639 @@
+use std::{collections::BTreeMap, ffi::OsStr};
+
+use clap::ValueEnum;
+use pwtrain::{Device, Direction, InitResult, LinkInfo, RouteSetting};
+use serde::Serialize;
+use serde_json::Value;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    /// Aligned columns with human readable rates and buffer sizes.
+    Table,
+    /// Raw values as a JSON array.
+    Json,
+    /// Raw values with a header row.
+    Csv,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GroupBy {
+    /// Sinks, then sources.
+    Direction,
+    /// The card the nodes belong to.
+    Card,
+    /// The backend, such as alsa or bluez5.
+    Api,
+}
+
+impl GroupBy {
+    fn heading(self, device: &Device) -> String {
+        match self {
+            GroupBy::Direction => device.direction_str().to_owned(),
+            GroupBy::Card => match (device.card(), device.card_name()) {
+                (Some(card), Some(name)) => format!("card {card}: {name}"),
+                (Some(card), None) => format!("card {card}"),
+                (None, _) => "(no card)".to_owned(),
+            },
+            GroupBy::Api => device.api().unwrap_or("(no api)").to_owned(),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GraphFormat {
+    /// Graphviz, to be rendered with e.g. `dot -Tpng`.
+    Dot,
+}
+
+/// How the table is laid out. The other formats ignore it, except for
+/// `compact_arrays`, which is about JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TableStyle {
+    pub color: bool,
+    /// Section the rows under a heading per group, instead of one flat table.
+    pub group_by: Option<GroupBy>,
+    /// Cut names and descriptions longer than this many columns, ending them with `…`.
+    pub max_width: Option<usize>,
+    /// In JSON, keep arrays of plain values such as rates and volumes on one line.
+    pub compact_arrays: bool,
+}
+
+/// What gets printed: the filtered devices plus where they came from.
+#[derive(Serialize)]
+pub struct Snapshot<'a> {
+    pub hostname: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<&'a str>,
+    pub devices: &'a [Device],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub routes: &'a [RouteSetting],
+    /// Node props to add to every device as extra columns, as `(prop key, column
+    /// name)`. A device without the prop gets an empty cell, or `null` in JSON.
+    #[serde(skip)]
+    pub fields: &'a [(String, String)],
+}
+
+/// `48000` as `48.0 kHz`.
+pub fn human_rate(rate: u32) -> String {
+    if rate == 0 {
+        return "-".to_owned();
+    }
+    format!("{:.1} kHz", f64::from(rate) / 1000.0)
+}
+
+/// `1024` at 48 kHz as `1024 smp (21.3 ms @ 48 kHz)`, or just the sample count when
+/// the rate is unknown.
+pub fn human_quantum(quantum: u32, rate: u32) -> String {
+    if rate == 0 {
+        return format!("{quantum} smp");
+    }
+    let ms = f64::from(quantum) * 1000.0 / f64::from(rate);
+    let khz = f64::from(rate) / 1000.0;
+    format!("{quantum} smp ({ms:.1} ms @ {khz} kHz)")
+}
+
+/// Whether to color what goes to a stream: not when `--no-color` or a non-empty
+/// `env_no_color`, the value of `NO_COLOR`, asks otherwise, nor when the stream is
+/// not a `terminal`.
+pub fn use_color(no_color: bool, env_no_color: Option<&OsStr>, terminal: bool) -> bool {
+    !no_color && env_no_color.is_none_or(OsStr::is_empty) && terminal
+}
+
+/// Wrap `text` in the SGR `code` when `color` is set.
+fn paint(text: String, code: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text
+    }
+}
+
+/// Shorten `text` to at most `max` terminal columns, ending it with `…` if cut.
+fn truncate(text: &str, max: Option<usize>) -> String {
+    let Some(max) = max else {
+        return text.to_owned();
+    };
+    if text.width() <= max {
+        return text.to_owned();
+    }
+    let mut out = String::new();
+    let mut width = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if width + char_width + 1 > max {
+            break;
+        }
+        width += char_width;
+        out.push(c);
+    }
+    if max > 0 {
+        out.push('…');
+    }
+    out
+}
+
+/// Left align `cell` in `width` terminal columns. Wide characters such as CJK and
+/// emoji take two columns, so this cannot go through `format!` padding.
+fn pad(cell: &str, width: usize) -> String {
+    let fill = width.saturating_sub(cell.width());
+    format!("{cell}{}", " ".repeat(fill))
+}
+
+fn table(devices: &[Device], fields: &[(String, String)], style: TableStyle) -> String {
+    let color = style.color;
+    let header: Vec<String> = [
+        "ID",
+        "NAME",
+        "DIRECTION",
+        "CHANNELS",
+        "RATE",
+        "QUANTUM",
+        "DESCRIPTION",
+    ]
+    .into_iter()
+    .map(str::to_owned)
+    .chain(fields.iter().map(|(_, column)| column.to_uppercase()))
+    .collect();
+    let rows: Vec<Vec<String>> = devices
+        .iter()
+        .map(|device| {
+            let mut row = vec![
+                device.id().to_string(),
+                truncate(device.node_name(), style.max_width),
+                device.direction_str().to_owned(),
+                device.channels().to_string(),
+                human_rate(device.rate()),
+                human_quantum(device.quantum(), device.rate()),
+                truncate(device.description(), style.max_width),
+            ];
+            row.extend(
+                fields
+                    .iter()
+                    .map(|(key, _)| truncate(device.prop(key).unwrap_or("-"), style.max_width)),
+            );
+            row
+        })
+        .collect();
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.width()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+    // Pad before painting so the escape codes do not count towards the widths.
+    let line = |row: &[String]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| pad(cell, *width))
+            .collect();
+        cells.join("  ").trim_end().to_owned()
+    };
+    // Sections keep the order in which their first device came up.
+    let mut sections: Vec<(Option<String>, Vec<usize>)> = vec![];
+    for (index, device) in devices.iter().enumerate() {
+        let heading = style.group_by.map(|group_by| group_by.heading(device));
+        match sections.iter_mut().find(|(known, _)| *known == heading) {
+            Some((_, members)) => members.push(index),
+            None => sections.push((heading, vec![index])),
+        }
+    }
+    let mut out = paint(line(&header), "1", color);
+    out.push('\n');
+    for (heading, members) in sections {
+        if let Some(heading) = heading {
+            out.push_str(&paint(format!("\n{heading}:"), "1", color));
+            out.push('\n');
+        }
+        for index in members {
+            let code = match devices[index].direction() {
+                Direction::Input => "36",
+                Direction::Output => "35",
+            };
+            out.push_str(&paint(line(&rows[index]), code, color));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The route-settings entries, one per line.
+fn routes(routes: &[RouteSetting]) -> String {
+    let mut out = "\nROUTES\n".to_owned();
+    for route in routes {
+        let volume = route
+            .volume()
+            .map(|volume| format!("{:.0}%", volume * 100.0))
+            .unwrap_or_else(|| "-".to_owned());
+        let mute = if route.mute() == Some(true) {
+            "  muted"
+        } else {
+            ""
+        };
+        out.push_str(&format!("{}  {volume}{mute}\n", route.key()));
+    }
+    out
+}
+
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The nodes as boxes, sinks and sources in their own colors, and every link as an
+/// edge labelled with the ports it connects.
+fn dot(devices: &[Device], links: &[LinkInfo]) -> String {
+    let mut nodes: BTreeMap<u32, (String, &str)> = BTreeMap::new();
+    for link in links {
+        nodes.insert(
+            link.output_node(),
+            (link.output_node_name().to_owned(), "gray"),
+        );
+        nodes.insert(
+            link.input_node(),
+            (link.input_node_name().to_owned(), "gray"),
+        );
+    }
+    for device in devices {
+        let color = match device.direction() {
+            Direction::Input => "steelblue",
+            Direction::Output => "darkorange",
+        };
+        nodes.insert(device.id(), (device.description().to_owned(), color));
+    }
+    let mut out = "digraph pipewire {\n    rankdir=LR;\n    node [shape=box];\n".to_owned();
+    for (id, (label, color)) in &nodes {
+        out.push_str(&format!(
+            "    n{id} [label={}, color={color}];\n",
+            dot_string(label)
+        ));
+    }
+    for link in links {
+        let label = format!("{} -> {}", link.output_port_name(), link.input_port_name());
+        out.push_str(&format!(
+            "    n{} -> n{} [label={}];\n",
+            link.output_node(),
+            link.input_node(),
+            dot_string(&label)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Render the links of `result` as a graph in `format`.
+pub fn render_graph(format: GraphFormat, result: &InitResult) -> String {
+    match format {
+        GraphFormat::Dot => dot(result.devices(), result.links()),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn csv(devices: &[Device], fields: &[(String, String)]) -> String {
+    let mut out = concat!(
+        "id,node_name,description,direction,channels,rate,quantum,",
+        "min_quantum,max_quantum,quantum_limit,requested_quantum"
+    )
+    .to_owned();
+    for (_, column) in fields {
+        out.push(',');
+        out.push_str(&csv_field(column));
+    }
+    out.push('\n');
+    for device in devices {
+        let mut row = vec![
+            device.id().to_string(),
+            csv_field(device.node_name()),
+            csv_field(device.description()),
+            device.direction_str().to_owned(),
+            device.channels().to_string(),
+            device.rate().to_string(),
+            device.quantum().to_string(),
+            device.min_quantum().to_string(),
+            device.max_quantum().to_string(),
+            device.limit_quantam().to_string(),
+            device
+                .requested_quantum()
+                .map(|quantum| quantum.to_string())
+                .unwrap_or_default(),
+        ];
+        row.extend(
+            fields
+                .iter()
+                .map(|(key, _)| csv_field(device.prop(key).unwrap_or_default())),
+        );
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+enum Piece {
+    Text(String),
+    Field(String),
+}
+
+/// A line of text to print per device, from `--output-template`.
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+/// A field of a device as a template prints it: strings bare, arrays joined with
+/// commas and missing values empty.
+fn template_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(template_field)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+impl Template {
+    /// Parse `text`, in which `{field}` stands for a device field by its JSON name,
+    /// such as `{id}` or `{node_name}`, and `{{` and `}}` for literal braces. The
+    /// column names of `fields` can be used too.
+    ///
+    /// An unknown field is an error listing the known ones.
+    pub fn parse(text: &str, fields: &[(String, String)]) -> Result<Self, String> {
+        let mut known: Vec<String> = match serde_json::to_value(Device::default()) {
+            Ok(Value::Object(map)) => map.keys().cloned().collect(),
+            _ => vec![],
+        };
+        known.extend(fields.iter().map(|(_, column)| column.clone()));
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let Some((name, rest)) = chars.as_str().split_once('}') else {
+                        return Err(format!("unclosed `{{` in template {text:?}"));
+                    };
+                    if !known.iter().any(|field| field == name) {
+                        return Err(format!(
+                            "unknown field `{name}` in template, expected one of: {}",
+                            known.join(", ")
+                        ));
+                    }
+                    pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                    pieces.push(Piece::Field(name.to_owned()));
+                    chars = rest.chars();
+                }
+                '}' => return Err(format!("unmatched `}}` in template {text:?}, write `}}}}`")),
+                c => literal.push(c),
+            }
+        }
+        pieces.push(Piece::Text(literal));
+        Ok(Self { pieces })
+    }
+
+    /// Format every device of `snapshot`, one per line.
+    pub fn render(&self, snapshot: &Snapshot) -> Result<String, String> {
+        let mut out = String::new();
+        for device in snapshot.devices {
+            let mut object = serde_json::to_value(device).map_err(|err| err.to_string())?;
+            for (key, column) in snapshot.fields {
+                object[column.as_str()] = device.prop(key).into();
+            }
+            for piece in &self.pieces {
+                match piece {
+                    Piece::Text(text) => out.push_str(text),
+                    Piece::Field(name) => out.push_str(&template_field(&object[name.as_str()])),
+                }
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
+
+/// Pretty-print `value` like `serde_json::to_string_pretty`, except that arrays
+/// holding no arrays or objects stay on one line.
+fn to_string_compact_arrays(value: &Value) -> String {
+    fn write(value: &Value, depth: usize, out: &mut String) {
+        let indent = |depth: usize| "  ".repeat(depth);
+        match value {
+            Value::Array(items)
+                if !items.iter().any(|item| item.is_array() || item.is_object()) =>
+            {
+                let items: Vec<String> = items.iter().map(Value::to_string).collect();
+                out.push_str(&format!("[{}]", items.join(", ")));
+            }
+            Value::Array(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    out.push_str(if index == 0 { "\n" } else { ",\n" });
+                    out.push_str(&indent(depth + 1));
+                    write(item, depth + 1, out);
+                }
+                out.push_str(&format!("\n{}]", indent(depth)));
+            }
+            Value::Object(map) if map.is_empty() => out.push_str("{}"),
+            Value::Object(map) => {
+                out.push('{');
+                for (index, (key, item)) in map.iter().enumerate() {
+                    out.push_str(if index == 0 { "\n" } else { ",\n" });
+                    out.push_str(&indent(depth + 1));
+                    out.push_str(&format!("{}: ", Value::from(key.as_str())));
+                    write(item, depth + 1, out);
+                }
+                out.push_str(&format!("\n{}}}", indent(depth)));
+            }
+            scalar => out.push_str(&scalar.to_string()),
+        }
+    }
+    let mut out = String::new();
+    write(value, 0, &mut out);
+    out
+}
+
+/// Render `snapshot` in `format`. `style` only affects the table.
+///
+/// JSON lists the devices by node name, then id, rather than in the order the
+/// server announced them, so that two snapshots of the same graph diff cleanly.
+/// Node names several devices share are listed under `name_collisions`.
+///
+/// The mapped [`fields`](Snapshot::fields) come from the raw props.
+pub fn render(format: Format, snapshot: &Snapshot, style: TableStyle) -> Result<String, String> {
+    let table = |devices| table(devices, snapshot.fields, style);
+    match format {
+        Format::Table if snapshot.routes.is_empty() => Ok(table(snapshot.devices)),
+        Format::Table => Ok(table(snapshot.devices) + &routes(snapshot.routes)),
+        Format::Json => {
+            let mut devices = snapshot.devices.to_vec();
+            devices.sort_by(|a, b| (a.node_name(), a.id()).cmp(&(b.node_name(), b.id())));
+            let snapshot = Snapshot {
+                devices: &devices,
+                ..*snapshot
+            };
+            let mut json = serde_json::to_value(&snapshot).map_err(|err| err.to_string())?;
+            if let Some(objects) = json["devices"].as_array_mut() {
+                for (object, device) in objects.iter_mut().zip(&devices) {
+                    for (key, column) in snapshot.fields {
+                        object[column.as_str()] = device.prop(key).into();
+                    }
+                }
+            }
+            let collisions = Device::name_collisions(&devices);
+            if !collisions.is_empty() {
+                json["name_collisions"] =
+                    serde_json::to_value(collisions).map_err(|err| err.to_string())?;
+            }
+            if style.compact_arrays {
+                return Ok(to_string_compact_arrays(&json) + "\n");
+            }
+            serde_json::to_string_pretty(&json)
+                .map(|json| json + "\n")
+                .map_err(|err| err.to_string())
+        }
+        Format::Csv => Ok(csv(snapshot.devices, snapshot.fields)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: u32, node_name: &str) -> Device {
+        Device::builder(id).node_name(node_name).build()
+    }
+
+    #[test]
+    fn json_does_not_depend_on_discovery_order() {
+        let discovered = [
+            device(57, "alsa_output.usb"),
+            device(40, "alsa_input.pci"),
+            device(63, "alsa_output.pci"),
+            device(41, "alsa_input.pci"),
+        ];
+        let render_json = |devices: &[Device]| {
+            let snapshot = Snapshot {
+                hostname: "host",
+                tag: None,
+                devices,
+                routes: &[],
+                fields: &[],
+            };
+            render(Format::Json, &snapshot, TableStyle::default()).unwrap()
+        };
+        let first = render_json(&discovered);
+        let mut reversed = discovered.clone();
+        reversed.reverse();
+        assert_eq!(first, render_json(&reversed));
+
+        let order: Vec<u64> = serde_json::from_str::<Value>(&first).unwrap()["devices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|device| device["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(order, [40, 41, 63, 57]);
+    }
+
+    #[test]
+    fn no_color_env_disables_ansi_escapes() {
+        let devices = [Device::default()];
+        let snapshot = Snapshot {
+            hostname: "host",
+            tag: None,
+            devices: &devices,
+            routes: &[],
+            fields: &[],
+        };
+        let style = TableStyle {
+            color: true,
+            ..Default::default()
+        };
+        assert!(
+            render(Format::Table, &snapshot, style)
+                .unwrap()
+                .contains('\x1b')
+        );
+
+        assert!(use_color(false, None, true));
+        assert!(use_color(false, Some(OsStr::new("")), true));
+        assert!(!use_color(false, None, false));
+        assert!(!use_color(true, None, true));
+        let color = use_color(false, Some(OsStr::new("1")), true);
+        let style = TableStyle {
+            color,
+            ..Default::default()
+        };
+        let table = render(Format::Table, &snapshot, style).unwrap();
+        assert!(!color);
+        assert!(!table.contains('\x1b'));
+    }
+
+    #[test]
+    fn compact_arrays_only_change_the_layout() {
+        let devices = [Device::builder(40)
+            .node_name("alsa_output.pci")
+            .rates(vec![44100, 48000])
+            .volumes(vec![0.5, 0.25], Some(false))
+            .build()];
+        let snapshot = Snapshot {
+            hostname: "host",
+            tag: None,
+            devices: &devices,
+            routes: &[],
+            fields: &[],
+        };
+        let pretty = render(Format::Json, &snapshot, TableStyle::default()).unwrap();
+        let style = TableStyle {
+            compact_arrays: true,
+            ..Default::default()
+        };
+        let compact = render(Format::Json, &snapshot, style).unwrap();
+        assert!(compact.contains("\"rates\": [44100, 48000]"));
+        assert!(compact.lines().count() < pretty.lines().count());
+        assert_eq!(
+            serde_json::from_str::<Value>(&compact).unwrap(),
+            serde_json::from_str::<Value>(&pretty).unwrap()
+        );
+    }
+
+    #[test]
+    fn template_fills_in_fields_and_escapes_braces() {
+        let devices = [Device::builder(40)
+            .node_name("alsa_output.pci")
+            .channels(2)
+            .rates(vec![44100, 48000])
+            .build()];
+        let snapshot = Snapshot {
+            hostname: "host",
+            tag: None,
+            devices: &devices,
+            routes: &[],
+            fields: &[],
+        };
+        let template = Template::parse("{{{id}}} {node_name} {channels}ch {rates} {api}", &[]);
+        assert_eq!(
+            template.unwrap().render(&snapshot).unwrap(),
+            "{40} alsa_output.pci 2ch 44100,48000 \n"
+        );
+        let err = Template::parse("{nmae}", &[]).err().unwrap();
+        assert!(err.contains("unknown field `nmae`") && err.contains("node_name"));
+        assert!(Template::parse("{id", &[]).is_err());
+        assert!(Template::parse("id}", &[]).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use pipewire::spa::{
+    param::{audio::AudioFormat, format::FormatProperties},
+    pod::{ChoiceValue, Object, Pod, Value, ValueArray, deserialize::PodDeserializer},
+    sys,
+    utils::{Choice, ChoiceEnum, Id},
+};
+
+/// Rates tried when a format only advertises a range.
+const COMMON_RATES: [u32; 13] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000, 352800, 384000,
+];
+
+pub(crate) fn pod_object(pod: &Pod) -> Option<Object> {
+    match PodDeserializer::deserialize_any_from(pod.as_bytes()) {
+        Ok((_, Value::Object(object))) => Some(object),
+        _ => None,
+    }
+}
+
+/// The sample rates an `EnumFormat` param accepts.
+pub(crate) fn format_rates(object: &Object) -> Vec<u32> {
+    let Some(property) = object
+        .properties
+        .iter()
+        .find(|property| property.key == FormatProperties::AudioRate.as_raw())
+    else {
+        return vec![];
+    };
+    let rates = match &property.value {
+        Value::Int(rate) => vec![*rate],
+        Value::Choice(ChoiceValue::Int(Choice(_, choice))) => match choice {
+            ChoiceEnum::None(rate) => vec![*rate],
+            ChoiceEnum::Enum { alternatives, .. } => alternatives.clone(),
+            ChoiceEnum::Range { min, max, .. } | ChoiceEnum::Step { min, max, .. } => COMMON_RATES
+                .iter()
+                .filter_map(|rate| i32::try_from(*rate).ok())
+                .filter(|rate| (*min..=*max).contains(rate))
+                .collect(),
+            _ => vec![],
+        },
+        _ => vec![],
+    };
+    rates
+        .into_iter()
+        .filter_map(|rate| u32::try_from(rate).ok())
+        .collect()
+}
+
+/// The sample rate a `Format` param fixes, i.e. the one a running node negotiated.
+pub(crate) fn format_rate(object: &Object) -> Option<u32> {
+    let property = object
+        .properties
+        .iter()
+        .find(|property| property.key == FormatProperties::AudioRate.as_raw())?;
+    let rate = match &property.value {
+        Value::Int(rate) => *rate,
+        Value::Choice(ChoiceValue::Int(Choice(_, ChoiceEnum::None(rate)))) => *rate,
+        _ => return None,
+    };
+    u32::try_from(rate).ok().filter(|rate| *rate != 0)
+}
+
+/// Names for the raw sample formats, in the spelling of `pw-dump`. The native
+/// endian aliases such as `S16` are left out, since they share their id with the
+/// explicit form.
+const SAMPLE_FORMATS: [(&str, AudioFormat); 22] = [
+    ("U8", AudioFormat::U8),
+    ("S8", AudioFormat::S8),
+    ("S16LE", AudioFormat::S16LE),
+    ("S16BE", AudioFormat::S16BE),
+    ("S24_32LE", AudioFormat::S24_32LE),
+    ("S24_32BE", AudioFormat::S24_32BE),
+    ("S32LE", AudioFormat::S32LE),
+    ("S32BE", AudioFormat::S32BE),
+    ("S24LE", AudioFormat::S24LE),
+    ("S24BE", AudioFormat::S24BE),
+    ("F32LE", AudioFormat::F32LE),
+    ("F32BE", AudioFormat::F32BE),
+    ("F64LE", AudioFormat::F64LE),
+    ("F64BE", AudioFormat::F64BE),
+    ("U8P", AudioFormat::U8P),
+    ("S8P", AudioFormat::S8P),
+    ("S16P", AudioFormat::S16P),
+    ("S24_32P", AudioFormat::S24_32P),
+    ("S32P", AudioFormat::S32P),
+    ("S24P", AudioFormat::S24P),
+    ("F32P", AudioFormat::F32P),
+    ("F64P", AudioFormat::F64P),
+];
+
+/// The sample format a `Format` or `EnumFormat` param settles on: the fixed value,
+/// or the default of a choice. `None` if it has none or it is not a raw format.
+pub(crate) fn format_sample_format(object: &Object) -> Option<String> {
+    let property = object
+        .properties
+        .iter()
+        .find(|property| property.key == FormatProperties::AudioFormat.as_raw())?;
+    let Id(raw) = match &property.value {
+        Value::Id(id) => *id,
+        Value::Choice(ChoiceValue::Id(Choice(_, choice))) => match choice {
+            ChoiceEnum::None(id) => *id,
+            ChoiceEnum::Enum { default, .. } => *default,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    SAMPLE_FORMATS
+        .iter()
+        .find(|(_, format)| format.as_raw() == raw)
+        .map(|(name, _)| (*name).to_owned())
+}
+
+/// The channel volumes and mute state a node reports in its `Props` param.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Volume {
+    pub(crate) channels: Vec<f32>,
+    pub(crate) mute: Option<bool>,
+}
+
+impl Volume {
+    /// Take over what `object`, a `Props` param, sets. A node can split its props
+    /// over several params, so fields it leaves out are kept.
+    pub(crate) fn update(&mut self, object: &Object) {
+        for property in &object.properties {
+            match (property.key, &property.value) {
+                (sys::SPA_PROP_channelVolumes, Value::ValueArray(ValueArray::Float(volumes))) => {
+                    self.channels = volumes.clone();
+                }
+                (sys::SPA_PROP_mute, Value::Bool(mute)) => self.mute = Some(*mute),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Extract the node name from a `default` metadata value such as `{ "name": "alsa_output.x" }`.
+///
+/// Never panics; a value it cannot make sense of gives `None`.
+pub(crate) fn default_node_name(value: &str) -> Option<String> {
+    let (_, rest) = value.split_once("\"name\"")?;
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    let mut name = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(name),
+            '\\' => name.push(chars.next()?),
+            c => name.push(c),
+        }
+    }
+    None
+}
+
+/// The channel names used in `audio.position` and their `SPA_AUDIO_CHANNEL_*` ids.
+const CHANNEL_POSITIONS: [(&str, u32); 37] = [
+    ("NA", sys::SPA_AUDIO_CHANNEL_NA),
+    ("MONO", sys::SPA_AUDIO_CHANNEL_MONO),
+    ("FL", sys::SPA_AUDIO_CHANNEL_FL),
+    ("FR", sys::SPA_AUDIO_CHANNEL_FR),
+    ("FC", sys::SPA_AUDIO_CHANNEL_FC),
+    ("LFE", sys::SPA_AUDIO_CHANNEL_LFE),
+    ("SL", sys::SPA_AUDIO_CHANNEL_SL),
+    ("SR", sys::SPA_AUDIO_CHANNEL_SR),
+    ("FLC", sys::SPA_AUDIO_CHANNEL_FLC),
+    ("FRC", sys::SPA_AUDIO_CHANNEL_FRC),
+    ("RC", sys::SPA_AUDIO_CHANNEL_RC),
+    ("RL", sys::SPA_AUDIO_CHANNEL_RL),
+    ("RR", sys::SPA_AUDIO_CHANNEL_RR),
+    ("TC", sys::SPA_AUDIO_CHANNEL_TC),
+    ("TFL", sys::SPA_AUDIO_CHANNEL_TFL),
+    ("TFC", sys::SPA_AUDIO_CHANNEL_TFC),
+    ("TFR", sys::SPA_AUDIO_CHANNEL_TFR),
+    ("TRL", sys::SPA_AUDIO_CHANNEL_TRL),
+    ("TRC", sys::SPA_AUDIO_CHANNEL_TRC),
+    ("TRR", sys::SPA_AUDIO_CHANNEL_TRR),
+    ("RLC", sys::SPA_AUDIO_CHANNEL_RLC),
+    ("RRC", sys::SPA_AUDIO_CHANNEL_RRC),
+    ("FLW", sys::SPA_AUDIO_CHANNEL_FLW),
+    ("FRW", sys::SPA_AUDIO_CHANNEL_FRW),
+    ("LFE2", sys::SPA_AUDIO_CHANNEL_LFE2),
+    ("FLH", sys::SPA_AUDIO_CHANNEL_FLH),
+    ("FCH", sys::SPA_AUDIO_CHANNEL_FCH),
+    ("FRH", sys::SPA_AUDIO_CHANNEL_FRH),
+    ("TFLC", sys::SPA_AUDIO_CHANNEL_TFLC),
+    ("TFRC", sys::SPA_AUDIO_CHANNEL_TFRC),
+    ("TSL", sys::SPA_AUDIO_CHANNEL_TSL),
+    ("TSR", sys::SPA_AUDIO_CHANNEL_TSR),
+    ("LLFE", sys::SPA_AUDIO_CHANNEL_LLFE),
+    ("RLFE", sys::SPA_AUDIO_CHANNEL_RLFE),
+    ("BC", sys::SPA_AUDIO_CHANNEL_BC),
+    ("BLC", sys::SPA_AUDIO_CHANNEL_BLC),
+    ("BRC", sys::SPA_AUDIO_CHANNEL_BRC),
+];
+
+/// Split a list prop, written as SPA JSON such as `[ 44100 48000 ]` or
+/// `[ "FL", "FR" ]`, or comma separated such as `FL,FR`, into its items. A scalar
+/// is a list of one, an empty list gives no items.
+pub(crate) fn prop_list(value: &str) -> Vec<&str> {
+    value
+        .split(|c: char| c == ',' || c == '[' || c == ']' || c.is_whitespace())
+        .map(|item| item.trim_matches('"'))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Parse an `audio.position` value such as `FL,FR` or `[ FL FR ]` into the channel
+/// names and their raw ids, index for index. Unknown names map to
+/// `SPA_AUDIO_CHANNEL_UNKNOWN`.
+pub(crate) fn channel_positions(value: &str) -> (Vec<String>, Vec<u32>) {
+    prop_list(value)
+        .into_iter()
+        .map(|name| (name.to_owned(), channel_position_id(name)))
+        .unzip()
+}
+
+fn channel_position_id(name: &str) -> u32 {
+    if let Some(aux) = name.strip_prefix("AUX").and_then(|n| n.parse::<u32>().ok()) {
+        return sys::SPA_AUDIO_CHANNEL_START_Aux + aux;
+    }
+    CHANNEL_POSITIONS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, id)| *id)
+        .unwrap_or(sys::SPA_AUDIO_CHANNEL_UNKNOWN)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn default_node_name_never_panics(value in any::<String>()) {
+            let _ = default_node_name(&value);
+        }
+
+        #[test]
+        fn prop_list_reads_back_every_form(items in prop::collection::vec("[a-zA-Z0-9_.]{1,12}", 0..6)) {
+            let forms = [
+                format!("[ {} ]", items.join(" ")),
+                format!("[ \"{}\" ]", items.join("\", \"")),
+                items.join(","),
+            ];
+            for form in &forms {
+                prop_assert_eq!(&prop_list(form), &items);
+            }
+        }
+
+        #[test]
+        fn default_node_name_reads_back_plain_names(name in "[a-zA-Z0-9_.:-]{0,40}") {
+            let value = format!("{{ \"name\": \"{name}\" }}");
+            prop_assert_eq!(default_node_name(&value), Some(name));
+        }
+    }
+}
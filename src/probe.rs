@@ -0,0 +1,89 @@
+use std::{thread, time::Duration};
+
+use crate::{
+    InitResult, PwError, init_roundtrip,
+    roundtrip::{MetadataWrite, set_metadata},
+};
+
+/// How often and how many times [`probe_rate`] looks for the graph to switch.
+const SETTLE_INTERVAL: Duration = Duration::from_millis(100);
+const SETTLE_ATTEMPTS: u32 = 20;
+
+/// The outcome of [`probe_rate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeResult {
+    requested: u32,
+    achieved: u32,
+}
+
+impl ProbeResult {
+    pub fn requested(&self) -> u32 {
+        self.requested
+    }
+    /// The rate the graph ran at while the rate was forced, see [`probe_rate`].
+    pub fn achieved(&self) -> u32 {
+        self.achieved
+    }
+    pub fn succeeded(&self) -> bool {
+        self.requested == self.achieved
+    }
+}
+
+/// Write `clock.force-rate`, removing it for 0 so that the graph picks the rate itself.
+fn force_rate(rate: u32) -> MetadataWrite {
+    MetadataWrite {
+        metadata: "settings",
+        key: "clock.force-rate",
+        type_: None,
+        value: (rate != 0).then(|| rate.to_string()),
+    }
+}
+
+/// The rate the graph runs at in `result`, as far as it tells: the one the running
+/// nodes negotiated, the first that differs from `requested` if they disagree, or
+/// when none runs, the effective rate of the settings.
+fn running_rate(result: &InitResult, requested: u32) -> u32 {
+    let rates: Vec<u32> = result
+        .devices()
+        .iter()
+        .filter_map(|device| device.running_rate())
+        .collect();
+    rates
+        .iter()
+        .find(|rate| **rate != requested)
+        .or(rates.first())
+        .copied()
+        .unwrap_or_else(|| result.settings().effective_rate())
+}
+
+/// Force the graph to `rate`, wait for it to switch, read back the rate it ended up
+/// at and then restore the previous `clock.force-rate`, removing the key if it was
+/// not set.
+///
+/// The achieved rate is the one the running nodes negotiated. While nothing runs
+/// no node has a format, so only the forced rate itself can be read back.
+///
+/// The previous value is restored even when forcing or reading back fails.
+pub fn probe_rate(rate: u32) -> Result<ProbeResult, PwError> {
+    let previous = init_roundtrip()?.settings().force_rate();
+
+    let probed = set_metadata(vec![force_rate(rate)]).and_then(|()| {
+        let mut achieved = running_rate(&init_roundtrip()?, rate);
+        for _ in 1..SETTLE_ATTEMPTS {
+            if achieved == rate {
+                break;
+            }
+            thread::sleep(SETTLE_INTERVAL);
+            achieved = running_rate(&init_roundtrip()?, rate);
+        }
+        Ok(achieved)
+    });
+    let restored = set_metadata(vec![force_rate(previous)]);
+
+    let achieved = probed?;
+    restored?;
+    Ok(ProbeResult {
+        requested: rate,
+        achieved,
+    })
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Direction, InitResult, PwError, init_roundtrip,
+    roundtrip::{MetadataWrite, set_metadata},
+};
+
+/// A snapshot of the forced clock settings and the default sink and source, which
+/// can be written to disk and re-applied later.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// `clock.force-rate`, 0 to stop forcing.
+    pub force_rate: Option<u32>,
+    /// `clock.force-quantum`, 0 to stop forcing.
+    pub force_quantum: Option<u32>,
+    /// The `node.name` of the default sink.
+    pub default_sink: Option<String>,
+    /// The `node.name` of the default source.
+    pub default_source: Option<String>,
+}
+
+impl Profile {
+    pub fn capture(result: &InitResult) -> Self {
+        let settings = result.settings();
+        Self {
+            force_rate: Some(settings.force_rate()),
+            force_quantum: Some(settings.force_quantum()),
+            default_sink: result.default_sink_name().map(str::to_owned),
+            default_source: result.default_source_name().map(str::to_owned),
+        }
+    }
+}
+
+/// Check `profile` against the live server and apply every entry that is still valid.
+///
+/// Invalid entries are skipped and described to `warn`. With `only_changed`,
+/// entries the server already has are skipped as well. Returns how many entries
+/// were applied.
+pub fn apply_profile(
+    profile: &Profile,
+    only_changed: bool,
+    mut warn: impl FnMut(String),
+) -> Result<usize, PwError> {
+    let current = init_roundtrip()?;
+    let settings = current.settings();
+    let mut writes = vec![];
+    let skip = |unchanged: bool| only_changed && unchanged;
+
+    if let Some(rate) = profile.force_rate
+        && !skip(rate == settings.force_rate())
+    {
+        if rate == 0 || settings.allow_rates().contains(&rate) || rate == settings.rate() {
+            writes.push(MetadataWrite {
+                metadata: "settings",
+                key: "clock.force-rate",
+                type_: None,
+                value: Some(rate.to_string()),
+            });
+        } else {
+            warn(format!("skipping force-rate {rate}: not an allowed rate"));
+        }
+    }
+    if let Some(quantum) = profile.force_quantum
+        && !skip(quantum == settings.force_quantum())
+    {
+        if quantum == 0 || (settings.min_quantum()..=settings.max_quantum()).contains(&quantum) {
+            writes.push(MetadataWrite {
+                metadata: "settings",
+                key: "clock.force-quantum",
+                type_: None,
+                value: Some(quantum.to_string()),
+            });
+        } else {
+            warn(format!(
+                "skipping force-quantum {quantum}: outside {}..={}",
+                settings.min_quantum(),
+                settings.max_quantum()
+            ));
+        }
+    }
+    let defaults = [
+        (
+            &profile.default_sink,
+            current.default_sink_name(),
+            Direction::Input,
+            "default.configured.audio.sink",
+        ),
+        (
+            &profile.default_source,
+            current.default_source_name(),
+            Direction::Output,
+            "default.configured.audio.source",
+        ),
+    ];
+    for (name, default, direction, key) in defaults {
+        let Some(name) = name else {
+            continue;
+        };
+        if skip(default == Some(name.as_str())) {
+            continue;
+        }
+        let exists = current
+            .devices()
+            .iter()
+            .any(|device| device.node_name() == name && device.direction() == direction);
+        if exists {
+            writes.push(MetadataWrite {
+                metadata: "default",
+                key,
+                type_: Some("Spa:String:JSON"),
+                value: Some(serde_json::json!({ "name": name }).to_string()),
+            });
+        } else {
+            warn(format!("skipping {key}: no device named {name}"));
+        }
+    }
+
+    let applied = writes.len();
+    if applied > 0 {
+        set_metadata(writes)?;
+    }
+    Ok(applied)
+}
@@ -0,0 +1,56 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use pwtrain::PwEvent;
+
+/// The last events of a watch, up to a fixed number, so that a long session keeps
+/// some history without growing without bound.
+pub struct RecentEvents {
+    capacity: usize,
+    events: VecDeque<(SystemTime, PwEvent)>,
+}
+
+impl RecentEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Keep `event`, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, event: &PwEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back((SystemTime::now(), event.clone()));
+    }
+
+    /// The kept events with when they arrived, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &(SystemTime, PwEvent)> {
+        self.events.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_the_newest_events() {
+        let mut recent = RecentEvents::new(2);
+        for id in 1..=5 {
+            recent.push(&PwEvent::DeviceRemoved(id));
+        }
+        let ids: Vec<u32> = recent
+            .iter()
+            .map(|(_, event)| match event {
+                PwEvent::DeviceRemoved(id) => *id,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(ids, [4, 5]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+    time::Duration,
+};
+
+use pipewire::{
+    self as pw,
+    properties::properties,
+    spa::{
+        self,
+        param::{
+            ParamType,
+            audio::{AudioFormat, AudioInfoRaw},
+        },
+        pod::Pod,
+    },
+    stream::{StreamBox, StreamFlags},
+};
+
+use crate::{Device, Direction, PwError, meter::format_param, roundtrip::connect};
+
+/// How long [`record`] waits past the requested duration for the last frames before
+/// it gives up and keeps what it has.
+const GRACE: Duration = Duration::from_secs(2);
+
+/// Interleaved signed 16 bit samples captured by [`record`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    rate: u32,
+    channels: u16,
+    samples: Vec<i16>,
+}
+
+impl Recording {
+    /// The negotiated sample rate.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+    /// The negotiated channel count.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+    /// The length of the recording, which falls short of the requested duration
+    /// when the node stopped delivering buffers.
+    pub fn duration(&self) -> Duration {
+        if self.rate == 0 || self.channels == 0 {
+            return Duration::ZERO;
+        }
+        let frames = self.samples.len() / usize::from(self.channels);
+        Duration::from_secs_f64(frames as f64 / f64::from(self.rate))
+    }
+
+    /// Write the recording as a 16 bit PCM WAV file.
+    pub fn write_wav(&self, mut out: impl Write) -> io::Result<()> {
+        let too_long = || io::Error::new(io::ErrorKind::InvalidInput, "recording too long for WAV");
+        let data_len = self
+            .samples
+            .len()
+            .checked_mul(2)
+            .and_then(|len| u32::try_from(len).ok())
+            .ok_or_else(too_long)?;
+        let riff_len = data_len.checked_add(36).ok_or_else(too_long)?;
+        let block_align = self.channels.checked_mul(2).ok_or_else(too_long)?;
+        let byte_rate = self
+            .rate
+            .checked_mul(u32::from(block_align))
+            .ok_or_else(too_long)?;
+        out.write_all(b"RIFF")?;
+        out.write_all(&riff_len.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // Format 1 is integer PCM.
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&self.channels.to_le_bytes())?;
+        out.write_all(&self.rate.to_le_bytes())?;
+        out.write_all(&byte_rate.to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&data_len.to_le_bytes())?;
+        for sample in &self.samples {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+        out.flush()
+    }
+}
+
+#[derive(Debug, Default)]
+struct RecordState {
+    format: AudioInfoRaw,
+    /// Frames still to capture, known once the format is.
+    remaining: Option<usize>,
+    recording: Recording,
+}
+
+impl RecordState {
+    fn set_format(&mut self, param: &Pod, duration: Duration) {
+        if self.format.parse(param).is_err() {
+            return;
+        }
+        let rate = self.format.rate();
+        let channels = u16::try_from(self.format.channels()).unwrap_or(0);
+        // A renegotiation mid stream would mix two layouts in one file, so only the
+        // first format counts.
+        if self.remaining.is_some() || rate == 0 || channels == 0 {
+            return;
+        }
+        self.recording.rate = rate;
+        self.recording.channels = channels;
+        self.remaining = Some((duration.as_secs_f64() * f64::from(rate)).round() as usize);
+    }
+
+    /// Append the whole frames in `bytes`, up to the requested length. Returns
+    /// whether the recording is complete.
+    fn process(&mut self, bytes: &[u8]) -> bool {
+        let Some(remaining) = &mut self.remaining else {
+            return false;
+        };
+        let channels = usize::from(self.recording.channels);
+        let frames = (bytes.len() / (2 * channels)).min(*remaining);
+        *remaining -= frames;
+        let samples = bytes[..frames * channels * 2]
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]));
+        self.recording.samples.extend(samples);
+        *remaining == 0
+    }
+}
+
+/// Capture `duration` of audio from `device` as 16 bit samples at its own rate and
+/// channel count.
+///
+/// Sinks are recorded through their monitor. The last buffer is cut to the frame
+/// the duration ends on. If the node stalls, what arrived until shortly after
+/// `duration` is returned. The capture stream is removed before returning.
+pub fn record(device: &Device, duration: Duration) -> Result<Recording, PwError> {
+    let (mainloop, core, _registry) = connect()?;
+
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Production",
+        *pw::keys::TARGET_OBJECT => device.node_name(),
+    };
+    if device.direction() == Direction::Input {
+        props.insert(*pw::keys::STREAM_CAPTURE_SINK, "true");
+    }
+    let stream = StreamBox::new(&core, "pwtrain-record", props).map_err(PwError::Stream)?;
+
+    let state = Rc::new(RefCell::new(RecordState::default()));
+    let _listener = stream
+        .add_local_listener_with_user_data(state.clone())
+        .param_changed(move |_, state, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != ParamType::Format.as_raw() {
+                return;
+            }
+            state.borrow_mut().set_format(param, duration);
+        })
+        .process({
+            let mainloop = mainloop.clone();
+            move |stream, state| {
+                let Some(mut buffer) = stream.dequeue_buffer() else {
+                    return;
+                };
+                let Some(data) = buffer.datas_mut().first_mut() else {
+                    return;
+                };
+                let offset = data.chunk().offset() as usize;
+                let size = data.chunk().size() as usize;
+                let Some(bytes) = data.data() else {
+                    return;
+                };
+                let start = offset.min(bytes.len());
+                let end = (offset + size).min(bytes.len());
+                if state.borrow_mut().process(&bytes[start..end]) {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register()
+        .map_err(PwError::Stream)?;
+
+    let format = format_param(AudioFormat::S16LE);
+    let mut params = [Pod::from_bytes(&format).expect("a serialized pod")];
+    stream
+        .connect(
+            spa::utils::Direction::Input,
+            None,
+            StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+            &mut params,
+        )
+        .map_err(PwError::Stream)?;
+
+    let stop = mainloop.loop_().add_timer({
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    let _ = stop.update_timer(Some(duration + GRACE), None);
+
+    mainloop.run();
+
+    let _ = stream.disconnect();
+    let recording = std::mem::take(&mut state.borrow_mut().recording);
+    Ok(recording)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_impossible_format_is_an_error_not_a_panic() {
+        let recording = Recording {
+            rate: u32::MAX,
+            channels: u16::MAX,
+            samples: vec![],
+        };
+        let err = recording.write_wav(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let recording = Recording {
+            rate: 48000,
+            channels: 2,
+            samples: vec![0; 4],
+        };
+        let mut wav = Vec::new();
+        recording.write_wav(&mut wav).unwrap();
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(wav[28..32], (48000u32 * 4).to_le_bytes());
+    }
+}
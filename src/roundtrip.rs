@@ -0,0 +1,1243 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    rc::Rc,
+    sync::Mutex,
+    time::Duration,
+};
+
+use pipewire::{
+    self as pw,
+    core::CoreRc,
+    device::DeviceListener,
+    main_loop::MainLoopRc,
+    metadata::{Metadata, MetadataListener},
+    node::{Node, NodeChangeMask, NodeInfoRef, NodeListener},
+    permissions::PermissionFlags,
+    properties::properties,
+    proxy::ProxyT,
+    registry::{GlobalObject, RegistryRc},
+    spa::{param::ParamType, pod::Pod, utils::dict::DictRef},
+    types::ObjectType,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Device, Direction, LinkInfo, PwError, RouteSetting, Settings,
+    bind::VersionedNode,
+    filter::glob_match,
+    link::Graph,
+    params::{default_node_name, format_rate, format_rates, format_sample_format, pod_object},
+    state::{Collected, Pending},
+};
+
+#[allow(dead_code)]
+pub(crate) enum Request {
+    Node(NodeListener),
+    Meta(MetadataListener),
+    Device(DeviceListener),
+}
+
+impl From<NodeListener> for Request {
+    fn from(value: NodeListener) -> Self {
+        Self::Node(value)
+    }
+}
+
+impl From<DeviceListener> for Request {
+    fn from(value: DeviceListener) -> Self {
+        Self::Device(value)
+    }
+}
+
+impl From<MetadataListener> for Request {
+    fn from(value: MetadataListener) -> Self {
+        Self::Meta(value)
+    }
+}
+
+/// Everything collected by one [`init_roundtrip`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InitResult {
+    devices: Vec<Device>,
+    settings: Settings,
+    default_sink: Option<String>,
+    default_source: Option<String>,
+    links: Vec<LinkInfo>,
+    unclassified_nodes: Vec<(u32, String)>,
+    hostname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cookie: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    routes: Vec<RouteSetting>,
+    #[serde(skip)]
+    metadata_checks: Vec<MetadataCheck>,
+    #[serde(skip)]
+    decisions: Vec<Decision>,
+    #[serde(skip)]
+    bind_failures: Vec<(u32, String)>,
+    #[serde(skip)]
+    old_versions: Vec<(u32, u32)>,
+    #[serde(skip)]
+    vanished: Vec<u32>,
+    truncated: bool,
+    complete: bool,
+}
+
+/// Why a metadata object was or was not read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataCheck {
+    id: u32,
+    name: Option<String>,
+    accepted: bool,
+    reason: String,
+}
+
+impl MetadataCheck {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// The `metadata.name` from the registry, if it had one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    pub fn accepted(&self) -> bool {
+        self.accepted
+    }
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Why a global the registry announced was or was not turned into a device or
+/// read as settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    id: u32,
+    kind: String,
+    media_class: Option<String>,
+    permissions: Option<String>,
+    kept: bool,
+    reason: String,
+}
+
+impl Decision {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// The object type, such as `Node` or `Metadata`.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+    pub fn media_class(&self) -> Option<&str> {
+        self.media_class.as_deref()
+    }
+    /// What this client may do with the object, see [`Device::permissions`]. `None`
+    /// for metadata, whose decisions are made after the fact.
+    pub fn permissions(&self) -> Option<&str> {
+        self.permissions.as_deref()
+    }
+    pub fn kept(&self) -> bool {
+        self.kept
+    }
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    fn new(global: &GlobalObject<&DictRef>, kept: bool, reason: impl Into<String>) -> Self {
+        let kind = match &global.type_ {
+            ObjectType::Other(other) => other.clone(),
+            known => format!("{known:?}"),
+        };
+        Self {
+            id: global.id,
+            kind,
+            media_class: global
+                .props
+                .and_then(|props| props.get("media.class"))
+                .map(str::to_owned),
+            permissions: Some(permission_string(global.permissions)),
+            kept,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// `flags` the way `pw-cli` shows them, e.g. `rwxm`, or `r-x-` for an object this
+/// client can read and call methods on but not write or set metadata on.
+pub(crate) fn permission_string(flags: PermissionFlags) -> String {
+    [
+        (PermissionFlags::R, 'r'),
+        (PermissionFlags::W, 'w'),
+        (PermissionFlags::X, 'x'),
+        (PermissionFlags::M, 'm'),
+    ]
+    .into_iter()
+    .map(|(flag, letter)| if flags.contains(flag) { letter } else { '-' })
+    .collect()
+}
+
+impl InitResult {
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+    pub fn into_devices(self) -> Vec<Device> {
+        self.devices
+    }
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+    /// The name of the machine the snapshot was taken on, or `unknown`.
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+    /// The label passed in [`InitOptions::tag`].
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+    /// The random cookie of the server instance, from its core info.
+    ///
+    /// A restarted server hands out a new cookie and reuses ids for other objects, so
+    /// when two snapshots differ in cookie none of the ids of one can be looked up in
+    /// the other, however alike their devices look. Reconnect logic should compare
+    /// this before trusting ids it kept.
+    pub fn cookie(&self) -> Option<u32> {
+        self.cookie
+    }
+    /// The entries of the `route-settings` metadata, ordered by key. Only read
+    /// with [`InitOptions::route_settings`].
+    pub fn routes(&self) -> &[RouteSetting] {
+        &self.routes
+    }
+    /// Every metadata object that was announced, and whether it was read.
+    pub fn metadata_checks(&self) -> &[MetadataCheck] {
+        &self.metadata_checks
+    }
+    /// The id of every global that could not be bound, with the error. Such objects
+    /// are left out rather than failing the snapshot.
+    pub fn bind_failures(&self) -> &[(u32, String)] {
+        &self.bind_failures
+    }
+    /// The id and `global.version` of every node or metadata the server offers at an
+    /// older interface version than was asked for, see [`InitOptions::node_version`].
+    /// Such objects are still read, but some of their params may be missing.
+    pub fn old_versions(&self) -> &[(u32, u32)] {
+        &self.old_versions
+    }
+    /// The id of every device node that was removed while its params were being
+    /// read. They are left out of [`devices`](Self::devices).
+    pub fn vanished(&self) -> &[u32] {
+        &self.vanished
+    }
+    /// What happened to every global the registry announced, ordered by id.
+    pub fn decisions(&self) -> &[Decision] {
+        &self.decisions
+    }
+    /// Whether the server answered every request. Only `false` for a partial result
+    /// kept after [`InitOptions::timeout`] ran out.
+    pub fn complete(&self) -> bool {
+        self.complete
+    }
+    /// Whether [`InitOptions::max_devices`] was hit and further devices were left out.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+    /// The id and `node.name` of every node without a `media.class`, which are never
+    /// turned into devices.
+    pub fn unclassified_nodes(&self) -> &[(u32, String)] {
+        &self.unclassified_nodes
+    }
+    /// Every link in the graph, including those between non-audio nodes.
+    pub fn links(&self) -> &[LinkInfo] {
+        &self.links
+    }
+    /// The `node.name` of the default sink, from the `default` metadata.
+    pub fn default_sink_name(&self) -> Option<&str> {
+        self.default_sink.as_deref()
+    }
+    /// The `node.name` of the default source, from the `default` metadata.
+    pub fn default_source_name(&self) -> Option<&str> {
+        self.default_source.as_deref()
+    }
+    /// The devices whose advertised rates leave out `rate`, so that they would have
+    /// to resample if the graph ran at it. Devices that advertise no rates are not
+    /// counted, since nothing is known about them.
+    pub fn devices_without_rate(&self, rate: u32) -> Vec<&Device> {
+        self.devices
+            .iter()
+            .filter(|device| device.supports_rate(rate) == Some(false))
+            .collect()
+    }
+    /// The rates every device in `direction` advertises, in ascending order, i.e. the
+    /// rates the graph could be forced to without any of them resampling.
+    ///
+    /// Devices that advertise no rates are left out. `None` if none in `direction`
+    /// advertises any; an empty list means they have no rate in common.
+    pub fn common_rates(&self, direction: Direction) -> Option<Vec<u32>> {
+        let mut known = self
+            .devices
+            .iter()
+            .filter(|device| device.direction() == direction && !device.rates().is_empty());
+        let mut common = known.next()?.rates().to_vec();
+        for device in known {
+            common.retain(|rate| device.rates().contains(rate));
+        }
+        common.sort_unstable();
+        common.dedup();
+        Some(common)
+    }
+    /// Whether `other` describes the same configuration, i.e. the same devices with
+    /// the same settings, defaults and route settings, however the graph happens to be
+    /// busy at the moment.
+    ///
+    /// The volatile fields left out are:
+    /// - the ids a server hands out and reuses: the device [`id`](Device::id),
+    ///   [`serial`](Device::serial), [`card`](Device::card),
+    ///   [`monitor_source_id`](Device::monitor_source_id) and
+    ///   [`members`](Device::members). Devices are paired by serial where both sides
+    ///   have the same one, i.e. within one server run, and otherwise by everything
+    ///   else, their node name first among it;
+    /// - what changes as streams come and go or the user turns a knob:
+    ///   [`volumes`](Device::volumes), [`mute`](Device::mute), the format a running
+    ///   node negotiated, [`running_rate`](Device::running_rate) and
+    ///   [`sample_format`](Device::sample_format), [`media_name`](Device::media_name),
+    ///   [`media_title`](Device::media_title), [`in_use`](Device::in_use) and the
+    ///   [`links`](Self::links);
+    /// - the raw [`props`](Device::props), which hold ids too and are not in a
+    ///   snapshot read back from JSON;
+    /// - where and how the snapshot was taken: the hostname, tag, cookie and whether
+    ///   it was complete or truncated.
+    pub fn semantically_eq(&self, other: &InitResult) -> bool {
+        if self.settings != other.settings
+            || self.default_sink != other.default_sink
+            || self.default_source != other.default_source
+            || self.routes != other.routes
+            || self.devices.len() != other.devices.len()
+        {
+            return false;
+        }
+        let mut unmatched: Vec<&Device> = other.devices.iter().collect();
+        let mut unpaired = vec![];
+        for device in &self.devices {
+            let by_serial = device.serial().and_then(|serial| {
+                unmatched
+                    .iter()
+                    .position(|other| other.serial() == Some(serial))
+            });
+            match by_serial {
+                Some(index) => {
+                    if unmatched.swap_remove(index).without_volatile() != device.without_volatile()
+                    {
+                        return false;
+                    }
+                }
+                None => unpaired.push(device),
+            }
+        }
+        let mut unmatched: Vec<Device> = unmatched
+            .into_iter()
+            .map(Device::without_volatile)
+            .collect();
+        unpaired.into_iter().all(|device| {
+            let device = device.without_volatile();
+            match unmatched.iter().position(|other| *other == device) {
+                Some(index) => {
+                    unmatched.swap_remove(index);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+    /// Put back the raw [`props`](Device::props) of every device, by id, such as ones
+    /// kept aside when the result went through JSON, which leaves them out. The
+    /// [`missing_props`](Device::missing_props) are worked out again from them.
+    pub fn restore_props(&mut self, mut props: BTreeMap<u32, BTreeMap<String, String>>) {
+        for device in &mut self.devices {
+            if let Some(props) = props.remove(&device.id()) {
+                device.set_props(props);
+            }
+        }
+    }
+    /// The device the default sink names. If several share that node name, the
+    /// first one listed is taken, see [`Device::name_collisions`].
+    pub fn default_sink(&self) -> Option<&Device> {
+        let name = self.default_sink.as_deref()?;
+        self.devices
+            .iter()
+            .find(|device| device.node_name() == name)
+    }
+    /// The device the default source names, the first one listed if several share it.
+    pub fn default_source(&self) -> Option<&Device> {
+        let name = self.default_source.as_deref()?;
+        self.devices
+            .iter()
+            .find(|device| device.node_name() == name)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Defaults {
+    sink: Option<String>,
+    source: Option<String>,
+}
+
+/// The remote set with [`set_remote`].
+static REMOTE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Connect every later call to the daemon socket `remote`, such as `pipewire-0`,
+/// instead of the one from `PIPEWIRE_REMOTE` or the default. `None` goes back to those.
+pub fn set_remote(remote: Option<&str>) {
+    *REMOTE.lock().unwrap_or_else(|err| err.into_inner()) = remote.map(str::to_owned);
+}
+
+/// Connect to the server, picking the remote the way `pw-cli` does: the one given
+/// to [`set_remote`], else `PIPEWIRE_REMOTE`, else libpipewire's default.
+pub(crate) fn connect() -> Result<(MainLoopRc, CoreRc, RegistryRc), PwError> {
+    pw::init();
+    let mainloop = pw::main_loop::MainLoopRc::new(None)?;
+    let context = pw::context::ContextRc::new(&mainloop, None)?;
+    let remote = REMOTE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .or_else(|| std::env::var("PIPEWIRE_REMOTE").ok())
+        .filter(|remote| !remote.is_empty());
+    let props = remote.map(|remote| properties! { *pw::keys::REMOTE_NAME => remote });
+    let core = context.connect_rc(props)?;
+    let registry = core.get_registry_rc()?;
+    Ok((mainloop, core, registry))
+}
+
+pub(crate) fn metadata_name<'a>(global: &GlobalObject<&'a DictRef>) -> Option<&'a str> {
+    global.props.and_then(|props| props.get("metadata.name"))
+}
+
+pub(crate) fn is_audio_node(global: &GlobalObject<&DictRef>) -> bool {
+    is_device_node(global, None)
+}
+
+/// Whether the node is an audio sink or source, or when `class` is given, whether
+/// its `media.class` matches that glob.
+pub(crate) fn is_device_node(global: &GlobalObject<&DictRef>, class: Option<&str>) -> bool {
+    let Some(media_class) = global.props.and_then(|props| props.get("media.class")) else {
+        return false;
+    };
+    match class {
+        None => Direction::from_media_class(media_class).is_some(),
+        Some(class) => glob_match(class, media_class),
+    }
+}
+
+/// Options for [`init_roundtrip_with`].
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    /// A glob on `media.class`, such as `Video/*`, selecting which nodes become
+    /// devices. `None` keeps only audio sinks and sources.
+    pub class: Option<String>,
+    /// A free form label copied into [`InitResult::tag`].
+    pub tag: Option<String>,
+    /// Stop binding device nodes after this many, leaving the rest out of the
+    /// result. Bounds the memory spent on a server with a runaway number of nodes.
+    pub max_devices: Option<usize>,
+    /// Give up waiting for the server after this long.
+    pub timeout: Option<Duration>,
+    /// What a roundtrip that ran into `timeout` returns.
+    pub timeout_action: TimeoutAction,
+    /// Also read the `route-settings` metadata into [`InitResult::routes`].
+    pub route_settings: bool,
+    /// Bind device nodes at this interface version instead of the one libpipewire was
+    /// built with, for a server that only offers some params at another version. A
+    /// node the server offers at a lower version is bound at that one.
+    pub node_version: Option<u32>,
+    /// Sync after every bind, as snapshots did before binds were batched behind one
+    /// trailing sync. Only slower, it is there to time both ways against each other.
+    pub sync_each_bind: bool,
+}
+
+/// What [`init_roundtrip_with`] does when [`InitOptions::timeout`] runs out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeoutAction {
+    /// Fail with [`PwError::Timeout`].
+    #[default]
+    Error,
+    /// Return what was collected so far, with [`InitResult::complete`] unset.
+    Partial,
+}
+
+pub fn init_roundtrip() -> Result<InitResult, PwError> {
+    init_roundtrip_with(&InitOptions::default())
+}
+
+pub fn init_roundtrip_with(opts: &InitOptions) -> Result<InitResult, PwError> {
+    let (mainloop, core, registry) = connect()?;
+    roundtrip(&mainloop, &core, &registry, opts, no_progress())
+}
+
+/// Take a snapshot and check whether any device has the `object.serial` `serial`.
+pub fn device_present(serial: u64) -> Result<bool, PwError> {
+    let result = init_roundtrip()?;
+    Ok(result
+        .devices()
+        .iter()
+        .any(|device| device.serial() == Some(serial)))
+}
+
+/// How far a [`snapshot_with_progress`] has come.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    discovered: usize,
+    pending: usize,
+}
+
+impl Progress {
+    /// How many globals the registry has announced so far.
+    pub fn discovered(&self) -> usize {
+        self.discovered
+    }
+    /// How many syncs are still unanswered. The snapshot is done when this reaches 0.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+pub(crate) type ProgressFn = Rc<RefCell<dyn FnMut(Progress)>>;
+
+pub(crate) fn no_progress() -> ProgressFn {
+    Rc::new(RefCell::new(|_| {}))
+}
+
+/// Like [`init_roundtrip_with`], calling `progress` on every `global` and `done` event.
+///
+/// `progress` runs on the calling thread, from inside the main loop, and must not
+/// start another roundtrip.
+pub fn snapshot_with_progress(
+    opts: &InitOptions,
+    progress: impl FnMut(Progress) + 'static,
+) -> Result<InitResult, PwError> {
+    let (mainloop, core, registry) = connect()?;
+    roundtrip(
+        &mainloop,
+        &core,
+        &registry,
+        opts,
+        Rc::new(RefCell::new(progress)),
+    )
+}
+
+/// Enumerate everything announced by `registry` and run `mainloop` until the server
+/// has answered every request.
+pub(crate) fn roundtrip(
+    mainloop: &MainLoopRc,
+    core: &CoreRc,
+    registry: &RegistryRc,
+    opts: &InitOptions,
+    progress: ProgressFn,
+) -> Result<InitResult, PwError> {
+    let core = core.clone();
+    let discovered = Rc::new(Cell::new(0));
+
+    // To comply with Rust's safety rules, we wrap this variable in an `Rc` and  a `Cell`.
+    let collected = Rc::new(Collected::default());
+    let requests = Rc::new(RefCell::new(vec![]));
+    let versioned: Rc<RefCell<Vec<VersionedNode>>> = Rc::new(RefCell::new(vec![]));
+    let settings = Rc::new(RefCell::new(Settings::default()));
+    let defaults = Rc::new(RefCell::new(Defaults::default()));
+    let routes: Rc<RefCell<BTreeMap<String, RouteSetting>>> =
+        Rc::new(RefCell::new(BTreeMap::new()));
+    let graph = Rc::new(RefCell::new(Graph::default()));
+    let unclassified = Rc::new(RefCell::new(vec![]));
+    let checks: Rc<RefCell<Vec<MetadataCheck>>> = Rc::new(RefCell::new(vec![]));
+    let decisions: Rc<RefCell<Vec<Decision>>> = Rc::new(RefCell::new(vec![]));
+    let bind_failures: Rc<RefCell<Vec<(u32, String)>>> = Rc::new(RefCell::new(vec![]));
+    let old_versions: Rc<RefCell<Vec<(u32, u32)>>> = Rc::new(RefCell::new(vec![]));
+    let vanished: Rc<RefCell<Vec<u32>>> = Rc::new(RefCell::new(vec![]));
+    let bound_nodes = Rc::new(Cell::new(0));
+    let truncated = Rc::new(Cell::new(false));
+    let loop_clone = mainloop.clone();
+
+    // Trigger the sync event. The server's answer won't be processed until we start the main loop,
+    // so we can safely do this before setting up a callback. This lets us avoid using a Cell.
+    let peddings = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+
+    peddings.push(pending);
+    // Binds do not sync one by one. Everything bound while a sync is in flight is
+    // covered by a single trailing sync issued once that one is answered, so a graph
+    // of a few hundred objects takes two round trips to the server instead of one per
+    // object. The server answers in order, so the trailing `done` still comes after
+    // every info, param and property event the binds caused. `pwtrain benchmark`
+    // with and without `--sync-each-bind` times the two against each other.
+    let unsynced = Rc::new(Cell::new(false));
+
+    let cookie = Rc::new(Cell::new(None));
+    let _listener_core = core
+        .add_listener_local()
+        .info({
+            let cookie = cookie.clone();
+            move |info| cookie.set(Some(info.cookie()))
+        })
+        .done({
+            let peddings = peddings.clone();
+            let progress = progress.clone();
+            let discovered = discovered.clone();
+            let unsynced = unsynced.clone();
+            let core = core.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                let Some(mut left) = peddings.complete(seq) else {
+                    return;
+                };
+                if left == 0 && unsynced.replace(false) {
+                    let pending = core.sync(0).expect("sync failed");
+                    peddings.push(pending);
+                    left = 1;
+                }
+                (progress.borrow_mut())(Progress {
+                    discovered: discovered.get(),
+                    pending: left,
+                });
+                if left != 0 {
+                    return;
+                }
+                loop_clone.quit();
+            }
+        })
+        .register();
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let collected = collected.clone();
+            let registry = registry.clone();
+            let requests = requests.clone();
+            let versioned = versioned.clone();
+            let node_version = opts.node_version;
+            let settings = settings.clone();
+            let defaults = defaults.clone();
+            let routes = routes.clone();
+            let route_settings = opts.route_settings;
+            let graph = graph.clone();
+            let class = opts.class.clone();
+            let unclassified = unclassified.clone();
+            let checks = checks.clone();
+            let peddings = peddings.clone();
+            let max_devices = opts.max_devices;
+            let truncated = truncated.clone();
+            let decisions = decisions.clone();
+            let bind_failures = bind_failures.clone();
+            let old_versions = old_versions.clone();
+            let unsynced = unsynced.clone();
+            let core = core.clone();
+            let sync_each_bind = opts.sync_each_bind;
+            move |global| {
+                // An object can go away between its `global` event and our bind, e.g. a
+                // stream that closes mid-snapshot. Such a bind either fails here or gives
+                // a proxy the server answers with an error and no events, while the sync
+                // still completes, so in both cases the object is simply missing from
+                // the result instead of taking the whole snapshot down.
+                let bind_failed = |err: String| {
+                    bind_failures.borrow_mut().push((global.id, err));
+                };
+                let check_version = |version: u32| {
+                    if global.version < version {
+                        old_versions.borrow_mut().push((global.id, global.version));
+                    }
+                };
+                let bound = || {
+                    if sync_each_bind {
+                        let pending = core.sync(0).expect("sync failed");
+                        peddings.push(pending);
+                    } else {
+                        unsynced.set(true);
+                    }
+                };
+                let decide = |kept: bool, reason: String| {
+                    decisions
+                        .borrow_mut()
+                        .push(Decision::new(global, kept, reason));
+                };
+                discovered.set(discovered.get() + 1);
+                (progress.borrow_mut())(Progress {
+                    discovered: discovered.get(),
+                    pending: peddings.len(),
+                });
+                graph.borrow_mut().add_global(global);
+                if global.type_ == pipewire::types::ObjectType::Node
+                    && let Some(props) = global.props
+                    && props.get("media.class").is_none()
+                {
+                    let name = props.get("node.name").unwrap_or("unknown");
+                    unclassified.borrow_mut().push((global.id, name.to_owned()));
+                }
+                match global.type_ {
+                    pipewire::types::ObjectType::Metadata => {
+                        let name = metadata_name(global);
+                        let index = checks.borrow().len();
+                        let read = match name {
+                            Some("settings" | "default") | None => true,
+                            Some("route-settings") => route_settings,
+                            Some(_) => false,
+                        };
+                        let (accepted, reason) = match name {
+                            Some(_) if read => (true, "named by its metadata.name".to_owned()),
+                            Some(other) => (false, format!("{other} is not a metadata we read")),
+                            None => (false, "no metadata.name and no clock.* keys".to_owned()),
+                        };
+                        checks.borrow_mut().push(MetadataCheck {
+                            id: global.id,
+                            name: name.map(str::to_owned),
+                            accepted,
+                            reason,
+                        });
+                        if !read {
+                            return;
+                        }
+                        let meta: Metadata = match registry.bind(global) {
+                            Ok(meta) => meta,
+                            Err(err) => {
+                                if let Some(check) = checks.borrow_mut().get_mut(index) {
+                                    check.accepted = false;
+                                    check.reason = format!("could not be bound: {err}");
+                                }
+                                bind_failed(err.to_string());
+                                return;
+                            }
+                        };
+                        check_version(pw::sys::PW_VERSION_METADATA);
+                        let listener = if name == Some("route-settings") {
+                            let routes = routes.clone();
+                            meta.add_listener_local()
+                                .property(move |_, key, _, value| {
+                                    let Some(key) = key else {
+                                        return 0;
+                                    };
+                                    let mut routes = routes.borrow_mut();
+                                    match value {
+                                        Some(value) => {
+                                            routes.insert(
+                                                key.to_owned(),
+                                                RouteSetting::parse(key, value),
+                                            );
+                                        }
+                                        None => {
+                                            routes.remove(key);
+                                        }
+                                    }
+                                    0
+                                })
+                                .register()
+                        } else if name != Some("default") {
+                            // Without a name, the metadata is taken for the settings as soon
+                            // as it publishes a clock key.
+                            let unnamed = name.is_none();
+                            let settings = settings.clone();
+                            let checks = checks.clone();
+                            meta.add_listener_local()
+                                .property(move |_, key, _, value| {
+                                    let (Some(key), Some(value)) = (key, value) else {
+                                        return 0;
+                                    };
+                                    if unnamed {
+                                        if !key.starts_with("clock.") {
+                                            return 0;
+                                        }
+                                        let check = &mut checks.borrow_mut()[index];
+                                        check.accepted = true;
+                                        check.reason =
+                                            "no metadata.name, but publishes clock.* keys"
+                                                .to_owned();
+                                    }
+                                    settings.borrow_mut().update(key, value);
+                                    0
+                                })
+                                .register()
+                        } else {
+                            let defaults = defaults.clone();
+                            meta.add_listener_local()
+                                .property(move |_, key, _, value| {
+                                    let name = value.and_then(default_node_name);
+                                    match key {
+                                        Some("default.audio.sink") => {
+                                            defaults.borrow_mut().sink = name
+                                        }
+                                        Some("default.audio.source") => {
+                                            defaults.borrow_mut().source = name
+                                        }
+                                        _ => {}
+                                    }
+                                    0
+                                })
+                                .register()
+                        };
+                        bound();
+                        requests
+                            .borrow_mut()
+                            .push((meta.upcast(), Request::Meta(listener)));
+                    }
+                    pipewire::types::ObjectType::Node => {
+                        let media_class = global.props.and_then(|props| props.get("media.class"));
+                        if !is_device_node(global, class.as_deref()) {
+                            let reason = match (media_class, class.as_deref()) {
+                                (None, _) => "no media.class".to_owned(),
+                                (Some(media_class), Some(class)) => {
+                                    format!("media.class={media_class} does not match {class}")
+                                }
+                                (Some(media_class), None) => format!(
+                                    "media.class={media_class} is not an audio sink or source"
+                                ),
+                            };
+                            decide(false, reason);
+                            return;
+                        }
+                        if max_devices.is_some_and(|max| bound_nodes.get() >= max) {
+                            truncated.set(true);
+                            decide(false, "the device cap was reached".to_owned());
+                            return;
+                        }
+                        let info_collected = collected.clone();
+                        let param_collected = collected.clone();
+                        let class = class.clone();
+                        let id = global.id;
+                        let info = move |info: &NodeInfoRef| {
+                            if !info.change_mask().contains(NodeChangeMask::PROPS) {
+                                return;
+                            }
+                            let Some(props) = info.props() else {
+                                return;
+                            };
+                            let Some(device) =
+                                Device::from_props(info.id(), props, class.as_deref())
+                            else {
+                                return;
+                            };
+                            info_collected.add_device(device);
+                        };
+                        let param = move |_: i32,
+                                          param_type: ParamType,
+                                          _: u32,
+                                          _: u32,
+                                          pod: Option<&Pod>| {
+                            let Some(object) = pod.and_then(pod_object) else {
+                                return;
+                            };
+                            match param_type {
+                                ParamType::EnumFormat => {
+                                    param_collected.add_rates(id, format_rates(&object));
+                                    if let Some(format) = format_sample_format(&object) {
+                                        param_collected.add_format(id, format, false);
+                                    }
+                                }
+                                ParamType::Format => {
+                                    if let Some(format) = format_sample_format(&object) {
+                                        param_collected.add_format(id, format, true);
+                                    }
+                                    if let Some(rate) = format_rate(&object) {
+                                        param_collected.set_running_rate(id, rate);
+                                    }
+                                }
+                                ParamType::Props => param_collected.update_volume(id, &object),
+                                _ => {}
+                            }
+                        };
+                        let params = [ParamType::EnumFormat, ParamType::Format, ParamType::Props];
+                        // The server refuses a version above its own, so a hint is capped
+                        // at that one.
+                        let wanted = node_version.unwrap_or(pw::sys::PW_VERSION_NODE);
+                        let version = wanted.min(global.version);
+                        let bound_node = match node_version {
+                            None => registry
+                                .bind::<Node, _>(global)
+                                .map_err(|err| err.to_string())
+                                .map(|node| {
+                                    let listener = node
+                                        .add_listener_local()
+                                        .info(info)
+                                        .param(param)
+                                        .register();
+                                    for param in params {
+                                        node.enum_params(0, Some(param), 0, u32::MAX);
+                                    }
+                                    requests
+                                        .borrow_mut()
+                                        .push((node.upcast(), Request::Node(listener)));
+                                }),
+                            Some(_) => VersionedNode::bind(&registry, id, version, info, param)
+                                .ok_or_else(|| format!("binding at version {version} failed"))
+                                .map(|node| {
+                                    for param in params {
+                                        node.enum_params(param);
+                                    }
+                                    versioned.borrow_mut().push(node);
+                                }),
+                        };
+                        if let Err(err) = bound_node {
+                            decide(false, format!("could not be bound: {err}"));
+                            bind_failed(err);
+                            return;
+                        }
+                        bound_nodes.set(bound_nodes.get() + 1);
+                        check_version(wanted);
+                        collected.set_permissions(global.id, permission_string(global.permissions));
+                        decide(
+                            true,
+                            format!("media.class={}", media_class.unwrap_or_default()),
+                        );
+                        bound();
+                    }
+                    pipewire::types::ObjectType::Port | pipewire::types::ObjectType::Link => {
+                        decide(false, "only used to name the links".to_owned());
+                    }
+                    _ => decide(false, "not a node or metadata".to_owned()),
+                }
+            }
+        })
+        .global_remove({
+            let collected = collected.clone();
+            let decisions = decisions.clone();
+            let vanished = vanished.clone();
+            // A device node that goes away before its params are in would be left
+            // with whatever part of them arrived, so it is dropped as a whole. The
+            // syncs it was covered by are still answered, so the loop ends as usual.
+            move |id| {
+                let mut decisions = decisions.borrow_mut();
+                let Some(decision) = decisions
+                    .iter_mut()
+                    .find(|decision| decision.id == id && decision.kept)
+                else {
+                    return;
+                };
+                decision.kept = false;
+                decision.reason = "removed before its params were read".to_owned();
+                collected.remove(id);
+                vanished.borrow_mut().push(id);
+            }
+        })
+        .register();
+
+    let timed_out = Rc::new(Cell::new(false));
+    let _timeout = opts.timeout.map(|timeout| {
+        let timer = mainloop.loop_().add_timer({
+            let mainloop = mainloop.clone();
+            let timed_out = timed_out.clone();
+            move |_| {
+                timed_out.set(true);
+                mainloop.quit();
+            }
+        });
+        let _ = timer.update_timer(Some(timeout), None);
+        timer
+    });
+
+    mainloop.run();
+
+    if timed_out.get() && opts.timeout_action == TimeoutAction::Error {
+        return Err(PwError::Timeout);
+    }
+    let settings = settings.take();
+    let mut devices = collected.take_devices(&settings);
+    let defaults = defaults.take();
+    let checks = checks.take();
+    // Metadata is only settled at the end, since unnamed objects are accepted once
+    // they publish clock keys, so its decisions come from the checks.
+    let mut decisions = decisions.take();
+    decisions.extend(checks.iter().map(|check| Decision {
+        id: check.id,
+        kind: "Metadata".to_owned(),
+        media_class: None,
+        permissions: None,
+        kept: check.accepted,
+        reason: check.reason.clone(),
+    }));
+    decisions.sort_by_key(|decision| decision.id);
+    Device::link_monitors(&mut devices);
+    let graph = graph.take();
+    Device::link_graph(&mut devices, &graph);
+    Ok(InitResult {
+        devices,
+        settings,
+        default_sink: defaults.sink,
+        default_source: defaults.source,
+        links: graph.into_links(),
+        unclassified_nodes: unclassified.take(),
+        hostname: hostname(),
+        tag: opts.tag.clone(),
+        cookie: cookie.get(),
+        routes: routes.take().into_values().collect(),
+        metadata_checks: checks,
+        decisions,
+        bind_failures: bind_failures.take(),
+        old_versions: old_versions.take(),
+        vanished: vanished.take(),
+        truncated: truncated.get(),
+        complete: !timed_out.get(),
+    })
+}
+
+/// One property to write with [`set_metadata`].
+pub(crate) struct MetadataWrite {
+    /// The `metadata.name` of the target metadata object.
+    pub(crate) metadata: &'static str,
+    pub(crate) key: &'static str,
+    pub(crate) type_: Option<&'static str>,
+    /// `None` removes the key.
+    pub(crate) value: Option<String>,
+}
+
+/// Write properties on subject 0 of the named metadata objects and wait until the
+/// server has processed them.
+pub(crate) fn set_metadata(writes: Vec<MetadataWrite>) -> Result<(), PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let requests = Rc::new(RefCell::new(vec![]));
+    let loop_clone = mainloop.clone();
+    let peddings = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+    peddings.push(pending);
+
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let peddings = peddings.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                if peddings.complete(seq) == Some(0) {
+                    loop_clone.quit();
+                }
+            }
+        })
+        .register();
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let registry = registry.clone();
+            let requests = requests.clone();
+            move |global| {
+                if global.type_ != pipewire::types::ObjectType::Metadata {
+                    return;
+                }
+                let Some(name) = metadata_name(global) else {
+                    return;
+                };
+                let mut writes = writes
+                    .iter()
+                    .filter(|write| write.metadata == name)
+                    .peekable();
+                if writes.peek().is_none() {
+                    return;
+                }
+                let Ok(meta) = registry.bind::<Metadata, _>(global) else {
+                    return;
+                };
+                for write in writes {
+                    meta.set_property(0, write.key, write.type_, write.value.as_deref());
+                }
+                let pending = core.sync(0).expect("sync failed");
+                peddings.push(pending);
+                requests.borrow_mut().push(meta);
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    Ok(())
+}
+
+/// Make the node named `name` the default sink or source by writing
+/// `default.audio.sink` or `default.audio.source` to the `default` metadata.
+///
+/// The name is not checked; look it up in a snapshot first.
+pub fn set_default(direction: Direction, name: &str) -> Result<(), PwError> {
+    let key = match direction {
+        Direction::Input => "default.audio.sink",
+        Direction::Output => "default.audio.source",
+    };
+    set_metadata(vec![MetadataWrite {
+        metadata: "default",
+        key,
+        type_: Some("Spa:String:JSON"),
+        value: Some(serde_json::json!({ "name": name }).to_string()),
+    }])
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .into_iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_owned())
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_rates_intersect_one_direction() {
+        let device = |id, media_class, rates| {
+            Device::builder(id)
+                .media_class(media_class)
+                .rates(rates)
+                .build()
+        };
+        let result = InitResult {
+            devices: vec![
+                device(40, "Audio/Sink", vec![96000, 44100, 48000]),
+                device(41, "Audio/Sink", vec![48000, 44100]),
+                device(42, "Audio/Sink", vec![]),
+                device(43, "Audio/Source", vec![16000]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            result.common_rates(Direction::Input),
+            Some(vec![44100, 48000])
+        );
+        assert_eq!(result.common_rates(Direction::Output), Some(vec![16000]));
+
+        let result = InitResult {
+            devices: vec![
+                device(40, "Audio/Sink", vec![44100]),
+                device(41, "Audio/Sink", vec![48000]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(result.common_rates(Direction::Input), Some(vec![]));
+        assert_eq!(result.common_rates(Direction::Output), None);
+    }
+
+    #[test]
+    fn semantic_equality_ignores_ids_order_and_volume() {
+        let device = |id, serial, name: &str, volume| {
+            Device::builder(id)
+                .serial(serial)
+                .node_name(name)
+                .media_class("Audio/Sink")
+                .volumes(vec![volume], Some(false))
+                .build()
+        };
+        let result = |devices| InitResult {
+            devices,
+            hostname: "host".to_owned(),
+            ..Default::default()
+        };
+        let before = result(vec![
+            device(40, 120, "hdmi", 1.0),
+            device(41, 121, "usb", 0.5),
+        ]);
+        let after = result(vec![
+            device(63, 310, "usb", 0.2),
+            device(57, 309, "hdmi", 1.0),
+        ]);
+        assert!(before.semantically_eq(&after));
+        assert_ne!(before, after);
+
+        let renamed = result(vec![
+            device(40, 120, "hdmi", 1.0),
+            device(41, 121, "usb2", 0.5),
+        ]);
+        assert!(!before.semantically_eq(&renamed));
+        let mut forced = after.clone();
+        forced.settings.force_rate = 96000;
+        assert!(!before.semantically_eq(&forced));
+    }
+
+    #[test]
+    fn semantic_equality_pairs_by_serial_and_ignores_the_running_format() {
+        let device = |serial, name: &str, format: Option<&str>| {
+            let device = Device::builder(40)
+                .serial(serial)
+                .node_name(name)
+                .media_class("Audio/Sink");
+            match format {
+                Some(format) => device.sample_format(format).build(),
+                None => device.build(),
+            }
+        };
+        let result = |devices| InitResult {
+            devices,
+            ..Default::default()
+        };
+        let idle = result(vec![device(120, "usb", None), device(121, "hdmi", None)]);
+        let running = result(vec![
+            device(121, "hdmi", Some("S32LE")),
+            device(120, "usb", Some("S16LE")),
+        ]);
+        assert!(idle.semantically_eq(&running));
+
+        // Same serials, so the devices are paired up as they are and found renamed,
+        // rather than matched by name across each other.
+        let swapped = result(vec![device(120, "hdmi", None), device(121, "usb", None)]);
+        assert!(!idle.semantically_eq(&swapped));
+    }
+
+    #[test]
+    fn json_round_trip_keeps_the_result() {
+        let mut settings = Settings::default();
+        for (key, value) in [
+            ("clock.rate", "48000"),
+            ("clock.allowed-rates", "[ 44100 48000 96000 ]"),
+            ("clock.quantum", "1024"),
+            ("clock.min-quantum", "32"),
+            ("clock.max-quantum", "2048"),
+            ("clock.force-rate", "96000"),
+        ] {
+            settings.update(key, value);
+        }
+        let device = Device::builder(40)
+            .node_name("alsa_output.pci")
+            .media_class("Audio/Sink")
+            .settings(&settings)
+            .rates(vec![96000, 44100, 48000])
+            .sample_format("S24_32LE")
+            .volumes(vec![0.5, 0.25], Some(false))
+            .build();
+        let result = InitResult {
+            devices: vec![device],
+            settings,
+            default_sink: Some("alsa_output.pci".to_owned()),
+            default_source: None,
+            links: vec![],
+            unclassified_nodes: vec![(42, "Video/Source".to_owned())],
+            hostname: "host".to_owned(),
+            tag: Some("before".to_owned()),
+            cookie: Some(0x5eed),
+            truncated: true,
+            complete: true,
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&result).unwrap();
+        let parsed: InitResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, result);
+    }
+
+    #[test]
+    fn restored_props_survive_json() {
+        let device = Device::builder(40)
+            .prop("node.name", "alsa_output.pci")
+            .prop("api.alsa.path", "hw:0")
+            .build();
+        let result = InitResult {
+            devices: vec![device],
+            ..Default::default()
+        };
+        let props = BTreeMap::from([(40, result.devices()[0].props().clone())]);
+        let mut parsed: InitResult =
+            serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert_eq!(parsed.devices()[0].prop("api.alsa.path"), None);
+
+        parsed.restore_props(props);
+        let device = &parsed.devices()[0];
+        assert_eq!(device.prop("api.alsa.path"), Some("hw:0"));
+        assert!(!device.missing_props().contains(&"node.name"));
+        assert!(device.missing_props().contains(&"audio.channels"));
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// One entry of the `route-settings` metadata, which the session manager keeps for
+/// cards whose volume belongs to a route, such as headphones and speakers on the
+/// same card, rather than to a node.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RouteSetting {
+    key: String,
+    volume: Option<f32>,
+    mute: Option<bool>,
+    channel_volumes: Vec<f32>,
+    value: String,
+}
+
+impl RouteSetting {
+    /// Read the JSON `value` stored under `key`. Fields it does not have, or which
+    /// are not what they should be, are left empty; the raw value is always kept.
+    pub(crate) fn parse(key: &str, value: &str) -> Self {
+        let json: Value = serde_json::from_str(value).unwrap_or_default();
+        let float = |value: &Value| value.as_f64().map(|value| value as f32);
+        Self {
+            key: key.to_owned(),
+            volume: json.get("volume").and_then(float),
+            mute: json.get("mute").and_then(Value::as_bool),
+            channel_volumes: json
+                .get("channelVolumes")
+                .and_then(Value::as_array)
+                .map(|volumes| volumes.iter().filter_map(float).collect())
+                .unwrap_or_default(),
+            value: value.to_owned(),
+        }
+    }
+
+    /// The metadata key, which names the card and the route.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// The overall volume as a linear factor.
+    pub fn volume(&self) -> Option<f32> {
+        self.volume
+    }
+    pub fn mute(&self) -> Option<bool> {
+        self.mute
+    }
+    /// The volume of each channel as a linear factor.
+    pub fn channel_volumes(&self) -> &[f32] {
+        &self.channel_volumes
+    }
+    /// The value as stored, for fields this type does not read.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
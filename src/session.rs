@@ -0,0 +1,69 @@
+use std::{
+    collections::BTreeSet,
+    thread,
+    time::{Duration, Instant},
+};
+
+use pipewire::{core::CoreRc, main_loop::MainLoopRc};
+
+use crate::{
+    Device, InitOptions, InitResult, PwError,
+    roundtrip::{connect, no_progress, roundtrip},
+};
+
+/// A connection that is kept open across snapshots, for callers that poll.
+///
+/// Each [`snapshot`](Self::snapshot) starts from empty state. It asks the core for a
+/// fresh registry, since a registry only announces the existing globals once, but
+/// the main loop, context and core are reused.
+pub struct Session {
+    mainloop: MainLoopRc,
+    core: CoreRc,
+}
+
+impl Session {
+    pub fn new() -> Result<Self, PwError> {
+        let (mainloop, core, _registry) = connect()?;
+        Ok(Self { mainloop, core })
+    }
+
+    pub fn snapshot(&self, opts: &InitOptions) -> Result<InitResult, PwError> {
+        let registry = self.core.get_registry_rc()?;
+        roundtrip(&self.mainloop, &self.core, &registry, opts, no_progress())
+    }
+
+    /// Take a snapshot every `interval` until two in a row hold the same devices, or
+    /// until `timeout` has passed.
+    ///
+    /// Devices are told apart by their serial and node name, so a node that comes
+    /// back with a new id counts as a change. Returns the last snapshot and whether
+    /// it was stable.
+    pub fn snapshot_until_stable(
+        &self,
+        opts: &InitOptions,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<(InitResult, bool), PwError> {
+        let deadline = Instant::now() + timeout;
+        let mut result = self.snapshot(opts)?;
+        loop {
+            if Instant::now() + interval > deadline {
+                return Ok((result, false));
+            }
+            thread::sleep(interval);
+            let next = self.snapshot(opts)?;
+            let stable = identities(next.devices()) == identities(result.devices());
+            result = next;
+            if stable {
+                return Ok((result, true));
+            }
+        }
+    }
+}
+
+fn identities(devices: &[Device]) -> BTreeSet<(Option<u64>, &str)> {
+    devices
+        .iter()
+        .map(|device| (device.serial(), device.node_name()))
+        .collect()
+}
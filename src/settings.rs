@@ -0,0 +1,337 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The global clock settings published in the `settings` metadata.
+///
+/// In JSON, unforced values are `null` rather than 0, and `effective_latency_ms`
+/// is added from the values the graph actually runs with.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SettingsJson", into = "SettingsJson")]
+pub struct Settings {
+    pub(crate) rate: u32,
+    pub(crate) allow_rates: Vec<u32>,
+    pub(crate) quantum: u32,
+    pub(crate) min_quantum: u32,
+    pub(crate) max_quantum: u32,
+    pub(crate) quantum_limit: u32,
+    pub(crate) force_rate: u32,
+    pub(crate) force_quantum: u32,
+}
+
+impl Settings {
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+    pub fn allow_rates(&self) -> &[u32] {
+        &self.allow_rates
+    }
+    pub fn quantum(&self) -> u32 {
+        self.quantum
+    }
+    pub fn min_quantum(&self) -> u32 {
+        self.min_quantum
+    }
+    pub fn max_quantum(&self) -> u32 {
+        self.max_quantum
+    }
+    /// `clock.quantum-limit`, the largest quantum the graph may ever use. This is the
+    /// graph-wide ceiling; [`Device::limit_quantam`](crate::Device::limit_quantam) is
+    /// only what a node was configured with. 0 when the server does not publish it.
+    pub fn quantum_limit(&self) -> u32 {
+        self.quantum_limit
+    }
+    /// The rate forced through `clock.force-rate`, 0 when not forced.
+    pub fn force_rate(&self) -> u32 {
+        self.force_rate
+    }
+    /// The quantum forced through `clock.force-quantum`, 0 when not forced.
+    pub fn force_quantum(&self) -> u32 {
+        self.force_quantum
+    }
+
+    /// The rate the graph runs at: the forced one if any, else `clock.rate`.
+    pub fn effective_rate(&self) -> u32 {
+        if self.force_rate != 0 {
+            self.force_rate
+        } else {
+            self.rate
+        }
+    }
+    /// The quantum the graph runs with: the forced one if any, else `clock.quantum`.
+    pub fn effective_quantum(&self) -> u32 {
+        if self.force_quantum != 0 {
+            self.force_quantum
+        } else {
+            self.quantum
+        }
+    }
+    /// How long one cycle of the graph takes, from the effective rate and quantum.
+    /// `None` while the rate is unknown.
+    pub fn effective_latency_ms(&self) -> Option<f64> {
+        let rate = self.effective_rate();
+        (rate != 0).then(|| f64::from(self.effective_quantum()) * 1000.0 / f64::from(rate))
+    }
+
+    /// Compare against `expected`, returning every value that differs.
+    pub fn compare(&self, expected: &ExpectedSettings) -> Vec<Mismatch> {
+        let checks = [
+            ("clock.rate", expected.rate, self.rate),
+            ("clock.quantum", expected.quantum, self.quantum),
+        ];
+        checks
+            .into_iter()
+            .filter_map(|(key, expected, actual)| {
+                let expected = expected?;
+                (expected != actual).then_some(Mismatch {
+                    key,
+                    expected,
+                    actual,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether `key` currently has `value`, with `value` read the way the metadata
+    /// spells it, e.g. `48000` or `[ 44100 48000 ]`. `None` when `key` is not one of
+    /// the clock settings or `value` does not parse.
+    pub fn has_value(&self, key: &str, value: &str) -> Option<bool> {
+        let number = || value.trim().parse::<u32>().ok();
+        let matches = match key {
+            "clock.rate" => number()? == self.rate,
+            "clock.quantum" => number()? == self.quantum,
+            "clock.min-quantum" => number()? == self.min_quantum,
+            "clock.max-quantum" => number()? == self.max_quantum,
+            "clock.quantum-limit" => number()? == self.quantum_limit,
+            "clock.force-rate" => number()? == self.force_rate,
+            "clock.force-quantum" => number()? == self.force_quantum,
+            "clock.allowed-rates" => {
+                let list = value.trim().strip_prefix('[')?.strip_suffix(']')?;
+                let rates: Vec<u32> = list
+                    .split_whitespace()
+                    .map(|rate| rate.parse().ok())
+                    .collect::<Option<_>>()?;
+                rates == self.allow_rates
+            }
+            _ => return None,
+        };
+        Some(matches)
+    }
+
+    /// Forget `key`, as when it is removed from the `settings` metadata, so that it
+    /// reads as unset again: 0, or no allowed rates. Returns whether the settings
+    /// changed.
+    pub(crate) fn remove(&mut self, key: &str) -> bool {
+        let old = self.clone();
+        match key {
+            "clock.rate" => self.rate = 0,
+            "clock.allowed-rates" => self.allow_rates = vec![],
+            "clock.quantum" => self.quantum = 0,
+            "clock.min-quantum" => self.min_quantum = 0,
+            "clock.max-quantum" => self.max_quantum = 0,
+            "clock.quantum-limit" => self.quantum_limit = 0,
+            "clock.force-rate" => self.force_rate = 0,
+            "clock.force-quantum" => self.force_quantum = 0,
+            _ => {}
+        }
+        *self != old
+    }
+
+    /// Apply one property of the `settings` metadata.
+    ///
+    /// Unknown keys and values that fail to parse are ignored. Returns whether
+    /// the settings changed.
+    ///
+    /// Keys and values come straight from the server, so this never panics,
+    /// whatever it is fed; a value it cannot parse leaves the settings as they were.
+    pub(crate) fn update(&mut self, key: &str, value: &str) -> bool {
+        let old = self.clone();
+        match key {
+            "clock.rate" => {
+                let Ok(rate) = value.parse() else {
+                    return false;
+                };
+                self.rate = rate;
+            }
+            "clock.allowed-rates" => {
+                let Some(list) = value.strip_prefix("[") else {
+                    return false;
+                };
+                let Some(list) = list.strip_suffix("]") else {
+                    return false;
+                };
+                let list = list.trim();
+                let list: Vec<&str> = list.split(' ').collect();
+                let mut allow_rates = vec![];
+                for rate in list {
+                    let Ok(rate) = rate.parse() else {
+                        return false;
+                    };
+                    allow_rates.push(rate);
+                }
+                self.allow_rates = allow_rates;
+            }
+            "clock.quantum" => {
+                let Ok(quantum) = value.parse() else {
+                    return false;
+                };
+                self.quantum = quantum;
+            }
+            "clock.min-quantum" => {
+                let Ok(min_quantum) = value.parse() else {
+                    return false;
+                };
+                self.min_quantum = min_quantum;
+            }
+            "clock.max-quantum" => {
+                let Ok(max_quantum) = value.parse() else {
+                    return false;
+                };
+                self.max_quantum = max_quantum;
+            }
+            "clock.quantum-limit" => {
+                let Ok(quantum_limit) = value.parse() else {
+                    return false;
+                };
+                self.quantum_limit = quantum_limit;
+            }
+            "clock.force-rate" => {
+                let Ok(force_rate) = value.parse() else {
+                    return false;
+                };
+                self.force_rate = force_rate;
+            }
+            "clock.force-quantum" => {
+                let Ok(force_quantum) = value.parse() else {
+                    return false;
+                };
+                self.force_quantum = force_quantum;
+            }
+            _ => {}
+        }
+        *self != old
+    }
+}
+
+/// How [`Settings`] looks in JSON.
+#[derive(Serialize, Deserialize)]
+struct SettingsJson {
+    rate: u32,
+    allow_rates: Vec<u32>,
+    quantum: u32,
+    min_quantum: u32,
+    max_quantum: u32,
+    quantum_limit: u32,
+    force_rate: Option<u32>,
+    force_quantum: Option<u32>,
+    /// Derived, so it is ignored when reading.
+    #[serde(default, skip_deserializing)]
+    effective_latency_ms: Option<f64>,
+}
+
+impl From<Settings> for SettingsJson {
+    fn from(settings: Settings) -> Self {
+        let forced = |value: u32| (value != 0).then_some(value);
+        Self {
+            effective_latency_ms: settings.effective_latency_ms(),
+            force_rate: forced(settings.force_rate),
+            force_quantum: forced(settings.force_quantum),
+            rate: settings.rate,
+            allow_rates: settings.allow_rates,
+            quantum: settings.quantum,
+            min_quantum: settings.min_quantum,
+            max_quantum: settings.max_quantum,
+            quantum_limit: settings.quantum_limit,
+        }
+    }
+}
+
+impl From<SettingsJson> for Settings {
+    fn from(json: SettingsJson) -> Self {
+        Self {
+            rate: json.rate,
+            allow_rates: json.allow_rates,
+            quantum: json.quantum,
+            min_quantum: json.min_quantum,
+            max_quantum: json.max_quantum,
+            quantum_limit: json.quantum_limit,
+            force_rate: json.force_rate.unwrap_or(0),
+            force_quantum: json.force_quantum.unwrap_or(0),
+        }
+    }
+}
+
+/// Values [`Settings::compare`] checks for. `None` fields are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectedSettings {
+    pub rate: Option<u32>,
+    pub quantum: Option<u32>,
+}
+
+/// One setting that did not have the expected value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub key: &'static str,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, got {}",
+            self.key, self.expected, self.actual
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn key() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just("clock.rate".to_owned()),
+            Just("clock.allowed-rates".to_owned()),
+            Just("clock.quantum".to_owned()),
+            Just("clock.min-quantum".to_owned()),
+            Just("clock.max-quantum".to_owned()),
+            Just("clock.quantum-limit".to_owned()),
+            Just("clock.force-rate".to_owned()),
+            Just("clock.force-quantum".to_owned()),
+            any::<String>(),
+        ]
+    }
+
+    fn value() -> impl Strategy<Value = String> {
+        prop_oneof![any::<String>(), "[\\[\\] 0-9-]{0,40}", "-?[0-9]{1,12}",]
+    }
+
+    proptest! {
+        #[test]
+        fn update_never_panics(updates in prop::collection::vec((key(), value()), 0..16)) {
+            let mut settings = Settings::default();
+            for (key, value) in updates {
+                let old = settings.clone();
+                let changed = settings.update(&key, &value);
+                prop_assert_eq!(changed, settings != old);
+                if !key.starts_with("clock.") {
+                    prop_assert_eq!(&settings, &old);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn removing_a_forced_rate_unforces_it() {
+        let mut settings = Settings::default();
+        assert!(settings.update("clock.force-rate", "96000"));
+        assert_eq!(settings.has_value("clock.force-rate", "0"), Some(false));
+        assert!(settings.remove("clock.force-rate"));
+        assert_eq!(settings.has_value("clock.force-rate", "0"), Some(true));
+        assert!(!settings.remove("clock.force-rate"));
+    }
+}
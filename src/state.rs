@@ -0,0 +1,197 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use pipewire::spa::{pod::Object, utils::result::AsyncSeq};
+
+use crate::{Device, Settings, params::Volume};
+
+// The listeners of a roundtrip share this state through `Rc`s. Every method below
+// borrows for its own duration only and calls nothing outside while it does, so
+// the order in which the server's events arrive cannot run into a borrow that is
+// still held, and callers never see a `RefCell` to hold on to.
+
+/// The core syncs still waiting for their `done`.
+#[derive(Debug, Default)]
+pub(crate) struct Pending(RefCell<Vec<AsyncSeq>>);
+
+impl Pending {
+    pub(crate) fn push(&self, seq: AsyncSeq) {
+        self.0.borrow_mut().push(seq);
+    }
+
+    /// Mark `seq` as answered. Returns how many syncs are left, or `None` if `seq`
+    /// was not one of them.
+    pub(crate) fn complete(&self, seq: AsyncSeq) -> Option<usize> {
+        let mut pending = self.0.borrow_mut();
+        let index = pending.iter().position(|known| *known == seq)?;
+        pending.remove(index);
+        Some(pending.len())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+}
+
+/// What the node listeners of one roundtrip collect, keyed by node id.
+#[derive(Debug, Default)]
+pub(crate) struct Collected {
+    devices: RefCell<Vec<Device>>,
+    rates: RefCell<HashMap<u32, Vec<u32>>>,
+    volumes: RefCell<HashMap<u32, Volume>>,
+    formats: RefCell<HashMap<u32, String>>,
+    running_rates: RefCell<HashMap<u32, u32>>,
+    permissions: RefCell<HashMap<u32, String>>,
+}
+
+impl Collected {
+    /// Add `device`, replacing the one with its id: a node that changes its props
+    /// during the roundtrip must not show up twice.
+    pub(crate) fn add_device(&self, device: Device) {
+        let mut devices = self.devices.borrow_mut();
+        devices.retain(|known| known.id() != device.id());
+        devices.push(device);
+    }
+
+    /// Forget node `id` and everything collected for it.
+    pub(crate) fn remove(&self, id: u32) {
+        self.devices.borrow_mut().retain(|device| device.id() != id);
+        self.rates.borrow_mut().remove(&id);
+        self.volumes.borrow_mut().remove(&id);
+        self.formats.borrow_mut().remove(&id);
+        self.running_rates.borrow_mut().remove(&id);
+        self.permissions.borrow_mut().remove(&id);
+    }
+
+    /// Remember the permissions node `id` was announced with.
+    pub(crate) fn set_permissions(&self, id: u32, permissions: String) {
+        self.permissions.borrow_mut().insert(id, permissions);
+    }
+
+    pub(crate) fn add_rates(&self, id: u32, rates: Vec<u32>) {
+        self.rates.borrow_mut().entry(id).or_default().extend(rates);
+    }
+
+    /// Keep the first format offered, the most preferred one, unless `negotiated`
+    /// says it is the one the node actually runs with.
+    pub(crate) fn add_format(&self, id: u32, format: String, negotiated: bool) {
+        let mut formats = self.formats.borrow_mut();
+        if negotiated {
+            formats.insert(id, format);
+        } else {
+            formats.entry(id).or_insert(format);
+        }
+    }
+
+    /// Remember the rate of the `Format` param node `id` negotiated.
+    pub(crate) fn set_running_rate(&self, id: u32, rate: u32) {
+        self.running_rates.borrow_mut().insert(id, rate);
+    }
+
+    /// Merge a `Props` param into the volume of node `id`.
+    pub(crate) fn update_volume(&self, id: u32, object: &Object) {
+        self.volumes
+            .borrow_mut()
+            .entry(id)
+            .or_default()
+            .update(object);
+    }
+
+    /// The devices with their params and `settings` filled in, in the order their
+    /// props last came in. Leaves the collection empty.
+    pub(crate) fn take_devices(&self, settings: &Settings) -> Vec<Device> {
+        let mut devices = self.devices.take();
+        let mut rates = self.rates.take();
+        let mut volumes = self.volumes.take();
+        let mut formats = self.formats.take();
+        let mut running_rates = self.running_rates.take();
+        let mut permissions = self.permissions.take();
+        for device in &mut devices {
+            device.apply_settings(settings);
+            if let Some(rates) = rates.remove(&device.id()) {
+                device.set_rates(rates);
+            }
+            if let Some(volume) = volumes.remove(&device.id()) {
+                device.set_volume(volume);
+            }
+            if let Some(format) = formats.remove(&device.id()) {
+                device.set_sample_format(format);
+            }
+            if let Some(rate) = running_rates.remove(&device.id()) {
+                device.set_running_rate(rate);
+            }
+            if let Some(permissions) = permissions.remove(&device.id()) {
+                device.set_permissions(permissions);
+            }
+        }
+        devices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    enum Event {
+        Props(u32),
+        Rates(u32, u32),
+        EnumFormat(u32, &'static str),
+        Format(u32, &'static str),
+    }
+
+    fn events() -> Vec<Event> {
+        (1..=3)
+            .flat_map(|id| {
+                [
+                    Event::Props(id),
+                    Event::Props(id),
+                    Event::Rates(id, 44100),
+                    Event::Rates(id, 48000),
+                    Event::EnumFormat(id, "S16LE"),
+                    Event::Format(id, "S24_32LE"),
+                ]
+            })
+            .collect()
+    }
+
+    proptest! {
+        // Events of different nodes interleave freely, and a node's params may be in
+        // before its props; only the sync at the end orders anything.
+        #[test]
+        fn callback_order_does_not_matter(events in Just(events()).prop_shuffle()) {
+            let collected = Collected::default();
+            for event in events {
+                match event {
+                    Event::Props(id) => collected.add_device(Device::builder(id).build()),
+                    Event::Rates(id, rate) => collected.add_rates(id, vec![rate]),
+                    Event::EnumFormat(id, format) => {
+                        collected.add_format(id, format.to_owned(), false)
+                    }
+                    Event::Format(id, format) => collected.add_format(id, format.to_owned(), true),
+                }
+            }
+            let mut devices = collected.take_devices(&Settings::default());
+            devices.sort_by_key(Device::id);
+            prop_assert_eq!(devices.iter().map(Device::id).collect::<Vec<_>>(), [1, 2, 3]);
+            for device in &devices {
+                prop_assert_eq!(device.rates(), [44100, 48000]);
+                prop_assert_eq!(device.sample_format(), Some("S24_32LE"));
+            }
+        }
+
+        #[test]
+        fn pending_drains_in_any_order(seqs in Just((0..8).collect::<Vec<i32>>()).prop_shuffle()) {
+            let pending = Pending::default();
+            for seq in 0..8 {
+                pending.push(AsyncSeq::from_seq(seq));
+            }
+            prop_assert_eq!(pending.complete(AsyncSeq::from_seq(100)), None);
+            for (done, seq) in seqs.into_iter().enumerate() {
+                prop_assert_eq!(pending.complete(AsyncSeq::from_seq(seq)), Some(7 - done));
+            }
+            prop_assert_eq!(pending.len(), 0);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    cell::{Cell, RefCell},
+    io::Cursor,
+    rc::Rc,
+};
+
+use pipewire::{
+    self as pw,
+    spa::{
+        pod::{Object, Pod, Value, serialize::PodSerializer},
+        spa_interface_call_method, sys as spa_sys,
+    },
+    types::ObjectType,
+};
+
+use crate::{PwError, bind::bind_node, roundtrip::connect, state::Pending};
+
+/// Suspend node `id`, as `pw-cli send-command <id> Suspend` does: the server stops
+/// it and closes the device behind it, clearing its format.
+///
+/// Returns whether the node exists. Nothing keeps it suspended, so the graph starts
+/// it again as soon as a stream is linked to it.
+pub fn suspend_node(id: u32) -> Result<bool, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let object = Object {
+        type_: spa_sys::SPA_TYPE_COMMAND_Node,
+        id: spa_sys::SPA_NODE_COMMAND_Suspend,
+        properties: vec![],
+    };
+    let command = PodSerializer::serialize(Cursor::new(Vec::new()), &Value::Object(object))
+        .expect("serializing a suspend command")
+        .0
+        .into_inner();
+    let found = Rc::new(Cell::new(false));
+    let bound: Rc<RefCell<Option<*mut pw::sys::pw_proxy>>> = Rc::new(RefCell::new(None));
+    let peddings = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+    peddings.push(pending);
+
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let peddings = peddings.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                if peddings.complete(seq) == Some(0) {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let registry = registry.clone();
+            let found = found.clone();
+            let bound = bound.clone();
+            let core = core.clone();
+            move |global| {
+                if global.type_ != ObjectType::Node || global.id != id {
+                    return;
+                }
+                let Some(pod) = Pod::from_bytes(&command) else {
+                    return;
+                };
+                // pipewire-rs has no `send_command`, so the node is bound and called
+                // through the C interface.
+                let Some(proxy) = bind_node(&registry, global.id, pw::sys::PW_VERSION_NODE) else {
+                    return;
+                };
+                let proxy = proxy.as_ptr();
+                // SAFETY: the bound proxy is a node proxy as asked for, and `pod` is a
+                // command object that outlives the call, which copies it into the message.
+                unsafe {
+                    spa_interface_call_method!(
+                        proxy,
+                        pw::sys::pw_node_methods,
+                        send_command,
+                        pod.as_raw_ptr().cast()
+                    );
+                }
+                found.set(true);
+                bound.replace(Some(proxy));
+                let pending = core.sync(0).expect("sync failed");
+                peddings.push(pending);
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    if let Some(proxy) = bound.take() {
+        // SAFETY: the proxy was bound above and is destroyed only here, before the
+        // core it belongs to goes away.
+        unsafe { pw::sys::pw_proxy_destroy(proxy) };
+    }
+    Ok(found.get())
+}
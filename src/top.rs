@@ -0,0 +1,121 @@
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use pwtrain::{InitOptions, InitResult, Session};
+
+use crate::output::human_quantum;
+
+/// Puts the terminal into a mode where single key presses, Ctrl-C included, can be
+/// read from stdin, and puts it back when dropped.
+struct Terminal {
+    raw: bool,
+}
+
+impl Terminal {
+    fn enter() -> Self {
+        // Ctrl-C arrives as a byte instead of a signal, so that the terminal always
+        // gets restored on the way out.
+        let raw = io::stdin().is_terminal() && stty(&["-icanon", "-echo", "-isig"]);
+        print!("\x1b[?25l");
+        Self { raw }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h");
+        let _ = io::stdout().flush();
+        if self.raw {
+            stty(&["icanon", "echo", "isig"]);
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> bool {
+    Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn screen(result: &InitResult, interval: Duration) -> String {
+    let settings = result.settings();
+    let mut out = format!(
+        "clock.rate {}  clock.quantum {}  refresh {:.1}s  q to quit\n\n",
+        settings.rate(),
+        human_quantum(settings.quantum(), settings.rate()),
+        interval.as_secs_f64()
+    );
+    out.push_str(&format!(
+        "{:>5}  {:<9}{:>7}  {:<5} NAME  CHANNELS\n",
+        "ID", "DIRECTION", "VOLUME", "MUTE"
+    ));
+    for device in result.devices() {
+        let volumes = device.volumes();
+        let volume = if volumes.is_empty() {
+            "-".to_owned()
+        } else {
+            let average = volumes.iter().sum::<f32>() / volumes.len() as f32;
+            format!("{:.0}%", average * 100.0)
+        };
+        let mute = match device.mute() {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "-",
+        };
+        let channels: Vec<String> = device
+            .channel_volumes()
+            .iter()
+            .map(|(channel, volume)| format!("{channel}: {volume:.2}"))
+            .collect();
+        out.push_str(&format!(
+            "{:>5}  {:<9}{:>7}  {:<5} {}  {}\n",
+            device.id(),
+            device.direction(),
+            volume,
+            mute,
+            device.node_name(),
+            channels.join(", ")
+        ));
+    }
+    out
+}
+
+/// Redraw the clock settings and devices every `interval` until `q` or Ctrl-C is pressed.
+pub fn top(opts: &InitOptions, interval: Duration) -> Result<(), String> {
+    let session = Session::new().map_err(|err| err.to_string())?;
+    let (quit, keys) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            match byte {
+                Ok(b'q' | b'Q' | 0x03) => {
+                    let _ = quit.send(());
+                    return;
+                }
+                Ok(_) => {}
+                Err(_) => return,
+            }
+        }
+    });
+
+    let _terminal = Terminal::enter();
+    loop {
+        let result = session.snapshot(opts).map_err(|err| err.to_string())?;
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "\x1b[H\x1b[2J{}", screen(&result, interval));
+        let _ = stdout.flush();
+        drop(stdout);
+        match keys.recv_timeout(interval) {
+            Ok(()) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) => {}
+            // stdin is closed, so only a signal can end the loop now.
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(interval),
+        }
+    }
+}
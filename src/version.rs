@@ -0,0 +1,40 @@
+use std::{cell::RefCell, ffi::CStr, rc::Rc};
+
+use pipewire as pw;
+
+use crate::{PwError, roundtrip::connect};
+
+/// The version of the `libpipewire` this binary is linked against.
+pub fn library_version() -> String {
+    // SAFETY: pipewire returns a pointer to a static, nul terminated string.
+    unsafe { CStr::from_ptr(pw::sys::pw_get_library_version()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The version reported by the connected server in its core info.
+pub fn server_version() -> Result<String, PwError> {
+    let (mainloop, core, _registry) = connect()?;
+
+    let version = Rc::new(RefCell::new(String::new()));
+    let pending = core.sync(0).expect("sync failed");
+    let _listener_core = core
+        .add_listener_local()
+        .info({
+            let version = version.clone();
+            move |info| *version.borrow_mut() = info.version().to_owned()
+        })
+        .done({
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    Ok(version.take())
+}
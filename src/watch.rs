@@ -0,0 +1,505 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use pipewire::{
+    self as pw,
+    loop_::Signal,
+    metadata::Metadata,
+    node::{Node, NodeChangeMask},
+    proxy::{Proxy, ProxyT},
+    spa::param::ParamType,
+    types::ObjectType,
+};
+
+use crate::{
+    Device, PwError, Settings,
+    params::{Volume, pod_object},
+    roundtrip::{Request, connect, is_audio_node, metadata_name},
+    state::Pending,
+};
+
+// Events are rare and handed straight to the handler, so boxing the device buys nothing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum PwEvent {
+    /// An audio sink or source appeared. Fired once per node, after its first info event.
+    DeviceAdded(Device),
+    /// A previously announced device changed. Carries the device as it is now and
+    /// what kind of change the server reported.
+    DeviceChanged(Device, DeviceChange),
+    /// A previously announced device went away. Carries the node id.
+    DeviceRemoved(u32),
+    /// One of the clock settings changed. Carries the full, updated settings.
+    SettingsChanged(Settings),
+    /// Everything that existed when the watch started has been reported. Fired once.
+    Synced,
+    /// The server came back after the connection to it was lost and everything it
+    /// has now has been reported again. Carries the fresh device list.
+    ///
+    /// Every device known before the loss was reported as removed when it happened, so
+    /// the devices in here were all announced with [`PwEvent::DeviceAdded`] just before.
+    Reconnected(Vec<Device>),
+    /// Every device and the settings as they are now, reported on request, see
+    /// [`WatchOptions::snapshot_on_sigusr1`].
+    Snapshot(Vec<Device>, Settings),
+}
+
+/// Which parts of a node a [`PwEvent::DeviceChanged`] is about. `props` and `state`
+/// are taken from the `change_mask` of the node info and can be set together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceChange {
+    /// The node props, e.g. its name or description. The device was rebuilt from them.
+    pub props: bool,
+    /// The `Props` param, i.e. the volume or mute. Set on its own, once the new param
+    /// arrived and was applied to the device.
+    pub params: bool,
+    /// The node state, e.g. it went from idle to running.
+    pub state: bool,
+}
+
+impl DeviceChange {
+    fn from_mask(mask: NodeChangeMask) -> Self {
+        Self {
+            props: mask.contains(NodeChangeMask::PROPS),
+            params: mask.contains(NodeChangeMask::PARAMS),
+            state: mask.contains(NodeChangeMask::STATE),
+        }
+    }
+}
+
+/// A cloneable flag that stops a running [`watch`].
+///
+/// It can be triggered from any thread, including from inside the event handler.
+#[derive(Debug, Clone, Default)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Stops the main loop once triggered.
+    pub stop: StopSignal,
+    /// How often the main loop checks `stop`.
+    pub stop_poll_interval: Duration,
+    /// Trigger `stop` on SIGINT and SIGTERM instead of letting them kill the process.
+    pub handle_signals: bool,
+    /// Stop on its own after this long, as if `stop` had been triggered.
+    pub timeout: Option<Duration>,
+    /// Only listen to the settings metadata and never bind nodes, so that only
+    /// [`PwEvent::SettingsChanged`] and [`PwEvent::Synced`] are reported.
+    pub settings_only: bool,
+    /// How many times in a row to try reconnecting after the server goes away, e.g.
+    /// because it restarted. With 0 a lost connection ends the watch with
+    /// [`PwError::Disconnected`].
+    pub reconnect_attempts: u32,
+    /// Report a [`PwEvent::Snapshot`] whenever the process gets SIGUSR1, once the
+    /// server has sent everything that happened before it. Unix only, like pipewire.
+    pub snapshot_on_sigusr1: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            stop: StopSignal::new(),
+            stop_poll_interval: Duration::from_millis(100),
+            handle_signals: false,
+            timeout: None,
+            settings_only: false,
+            reconnect_attempts: 0,
+            snapshot_on_sigusr1: false,
+        }
+    }
+}
+
+/// Run the pipewire main loop and report device and settings changes to `handler`
+/// until `opts.stop` is triggered.
+///
+/// The handler is called on the thread that called `watch`, from inside the pipewire
+/// callbacks, so it does not need to be `Send`. It must not block for long, since no
+/// further events are dispatched while it runs, and it must not call `watch` or
+/// [`init_roundtrip`](crate::init_roundtrip) itself: those run their own main loop and
+/// would re-enter the handler.
+///
+/// If the server goes away, the devices are reported as removed and, as long as
+/// `opts.reconnect_attempts` allows, the watch connects again every
+/// [`RECONNECT_DELAY`] and reports what it finds with [`PwEvent::Reconnected`].
+pub fn watch(opts: WatchOptions, handler: impl FnMut(PwEvent) + 'static) -> Result<(), PwError> {
+    let handler: Rc<RefCell<dyn FnMut(PwEvent)>> = Rc::new(RefCell::new(handler));
+    let deadline = opts.timeout.map(|timeout| Instant::now() + timeout);
+    let mut reconnected = false;
+    let mut attempts = 0;
+    loop {
+        match watch_connection(&opts, handler.clone(), deadline, reconnected) {
+            Ok(false) => return Ok(()),
+            Ok(true) if opts.reconnect_attempts == 0 => return Err(PwError::Disconnected),
+            Ok(true) => attempts = 0,
+            Err(err) if !reconnected || attempts >= opts.reconnect_attempts => return Err(err),
+            Err(_) => {}
+        }
+        attempts += 1;
+        reconnected = true;
+        let retry_at = Instant::now() + RECONNECT_DELAY;
+        while Instant::now() < retry_at {
+            if opts.stop.is_stopped() {
+                return Ok(());
+            }
+            thread::sleep(opts.stop_poll_interval.min(RECONNECT_DELAY));
+        }
+    }
+}
+
+/// How long [`watch`] waits before each attempt to reconnect.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// What the core reports as the error of a connection the server closed.
+const EPIPE: i32 = 32;
+
+/// Watch over one connection. Returns whether it ended because the server went away.
+fn watch_connection(
+    opts: &WatchOptions,
+    handler: Rc<RefCell<dyn FnMut(PwEvent)>>,
+    deadline: Option<Instant>,
+    reconnected: bool,
+) -> Result<bool, PwError> {
+    let (mainloop, core, registry) = connect()?;
+
+    let emit = move |event: PwEvent| (handler.borrow_mut())(event);
+    let settings = Rc::new(RefCell::new(Settings::default()));
+    // Keeps the bound proxies and their listeners alive until the global goes away.
+    let requests: Rc<RefCell<HashMap<u32, (Proxy, Request)>>> =
+        Rc::new(RefCell::new(HashMap::new()));
+    let announced: Rc<RefCell<BTreeMap<u32, Device>>> = Rc::new(RefCell::new(BTreeMap::new()));
+    // Syncs issued before `Synced` went out; every bind adds one so that its first
+    // info or property events are in before the initial state counts as complete.
+    let peddings = Rc::new(Pending::default());
+    let synced = Rc::new(Cell::new(false));
+    let disconnected = Rc::new(Cell::new(false));
+    // Syncs issued on SIGUSR1; the snapshot goes out once one is answered.
+    let snapshots = Rc::new(Pending::default());
+    let pending = core.sync(0).expect("sync failed");
+    peddings.push(pending);
+
+    let _listener_core = core
+        .add_listener_local()
+        .done({
+            let peddings = peddings.clone();
+            let synced = synced.clone();
+            let announced = announced.clone();
+            let settings = settings.clone();
+            let snapshots = snapshots.clone();
+            let emit = emit.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                if snapshots.complete(seq).is_some() {
+                    let devices = announced.borrow().values().cloned().collect();
+                    let settings = settings.borrow().clone();
+                    emit(PwEvent::Snapshot(devices, settings));
+                    return;
+                }
+                if synced.get() {
+                    return;
+                }
+                if peddings.complete(seq) != Some(0) {
+                    return;
+                }
+                synced.set(true);
+                if reconnected {
+                    let devices = announced.borrow().values().cloned().collect();
+                    emit(PwEvent::Reconnected(devices));
+                } else {
+                    emit(PwEvent::Synced);
+                }
+            }
+        })
+        .error({
+            let mainloop = mainloop.clone();
+            let disconnected = disconnected.clone();
+            move |id, _seq, res, _message| {
+                if id == pw::core::PW_ID_CORE && res == -EPIPE {
+                    disconnected.set(true);
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    let initial_sync = {
+        let core = core.clone();
+        move || {
+            if !synced.get() {
+                let pending = core.sync(0).expect("sync failed");
+                peddings.push(pending);
+            }
+        }
+    };
+
+    let _listener_reg = registry
+        .add_listener_local()
+        .global({
+            let registry = registry.clone();
+            let requests = requests.clone();
+            let announced = announced.clone();
+            let settings = settings.clone();
+            let emit = emit.clone();
+            let initial_sync = initial_sync.clone();
+            let settings_only = opts.settings_only;
+            move |global| match global.type_ {
+                ObjectType::Metadata => {
+                    // Unnamed metadata is read too; only its clock.* keys reach the settings.
+                    if !matches!(metadata_name(global), Some("settings") | None) {
+                        return;
+                    }
+                    let Ok(meta_settings) = registry.bind::<Metadata, _>(global) else {
+                        return;
+                    };
+                    let settings = settings.clone();
+                    let emit = emit.clone();
+                    let listener = meta_settings
+                        .add_listener_local()
+                        .property(move |_, key, _, value| {
+                            let mut current = settings.borrow_mut();
+                            let changed = match (key, value) {
+                                (Some(key), Some(value)) => current.update(key, value),
+                                (Some(key), None) => current.remove(key),
+                                (None, _) => false,
+                            };
+                            drop(current);
+                            if changed {
+                                let settings = settings.borrow().clone();
+                                emit(PwEvent::SettingsChanged(settings));
+                            }
+                            0
+                        })
+                        .register();
+                    requests
+                        .borrow_mut()
+                        .insert(global.id, (meta_settings.upcast(), listener.into()));
+                    initial_sync();
+                }
+                ObjectType::Node => {
+                    if settings_only || !is_audio_node(global) {
+                        return;
+                    }
+                    let Ok(node) = registry.bind::<Node, _>(global) else {
+                        return;
+                    };
+                    let current: Rc<RefCell<Option<Device>>> = Rc::new(RefCell::new(None));
+                    let volume = Rc::new(RefCell::new(Volume::default()));
+                    let listener = node
+                        .add_listener_local()
+                        .info({
+                            let announced = announced.clone();
+                            let settings = settings.clone();
+                            let emit = emit.clone();
+                            let current = current.clone();
+                            let volume = volume.clone();
+                            move |info| {
+                                // New params are reported by the param listener below once
+                                // they arrive, the device here would still have the old ones.
+                                let change = DeviceChange {
+                                    params: false,
+                                    ..DeviceChange::from_mask(info.change_mask())
+                                };
+                                if change == DeviceChange::default() {
+                                    return;
+                                }
+                                // Only a props change can alter what the device is built from.
+                                let device = match (change.props, info.props()) {
+                                    (true, Some(props)) => {
+                                        let Some(mut device) =
+                                            Device::from_props(info.id(), props, None)
+                                        else {
+                                            return;
+                                        };
+                                        device.apply_settings(&settings.borrow());
+                                        device.set_volume(volume.borrow().clone());
+                                        device
+                                    }
+                                    _ => match current.borrow().clone() {
+                                        Some(device) => device,
+                                        None => return,
+                                    },
+                                };
+                                current.replace(Some(device.clone()));
+                                let known =
+                                    announced.borrow_mut().insert(device.id(), device.clone());
+                                if known.is_none() {
+                                    emit(PwEvent::DeviceAdded(device));
+                                } else {
+                                    emit(PwEvent::DeviceChanged(device, change));
+                                }
+                            }
+                        })
+                        .param({
+                            let announced = announced.clone();
+                            let emit = emit.clone();
+                            move |_, param_type, _, _, pod| {
+                                if param_type != ParamType::Props {
+                                    return;
+                                }
+                                let Some(object) = pod.and_then(pod_object) else {
+                                    return;
+                                };
+                                let mut updated = volume.borrow().clone();
+                                updated.update(&object);
+                                if volume.replace(updated.clone()) == updated {
+                                    return;
+                                }
+                                let Some(mut device) = current.borrow().clone() else {
+                                    return;
+                                };
+                                device.set_volume(updated);
+                                current.replace(Some(device.clone()));
+                                announced.borrow_mut().insert(device.id(), device.clone());
+                                let change = DeviceChange {
+                                    params: true,
+                                    ..Default::default()
+                                };
+                                emit(PwEvent::DeviceChanged(device, change));
+                            }
+                        })
+                        .register();
+                    // The server sends the `Props` param again whenever it changes.
+                    node.subscribe_params(&[ParamType::Props]);
+                    requests
+                        .borrow_mut()
+                        .insert(global.id, (node.upcast(), listener.into()));
+                    initial_sync();
+                }
+                _ => {}
+            }
+        })
+        .global_remove({
+            let requests = requests.clone();
+            let announced = announced.clone();
+            let emit = emit.clone();
+            move |id| {
+                requests.borrow_mut().remove(&id);
+                if announced.borrow_mut().remove(&id).is_some() {
+                    emit(PwEvent::DeviceRemoved(id));
+                }
+            }
+        })
+        .register();
+
+    let timer = mainloop.loop_().add_timer({
+        let mainloop = mainloop.clone();
+        let stop = opts.stop.clone();
+        move |_| {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                stop.stop();
+            }
+            if stop.is_stopped() {
+                mainloop.quit();
+            }
+        }
+    });
+    let _ = timer.update_timer(Some(opts.stop_poll_interval), Some(opts.stop_poll_interval));
+
+    let on_signal = {
+        let mainloop = mainloop.clone();
+        let stop = opts.stop.clone();
+        move || {
+            stop.stop();
+            mainloop.quit();
+        }
+    };
+    let _signals = opts.handle_signals.then(|| {
+        [Signal::SIGINT, Signal::SIGTERM]
+            .map(|signal| mainloop.loop_().add_signal_local(signal, on_signal.clone()))
+    });
+    let _snapshot_signal = opts.snapshot_on_sigusr1.then(|| {
+        let core = core.clone();
+        mainloop.loop_().add_signal_local(Signal::SIGUSR1, move || {
+            let pending = core.sync(0).expect("sync failed");
+            snapshots.push(pending);
+        })
+    });
+
+    if !opts.stop.is_stopped() {
+        mainloop.run();
+    }
+    if !disconnected.get() {
+        return Ok(false);
+    }
+    let gone: Vec<u32> = announced.borrow().keys().copied().collect();
+    for id in gone {
+        emit(PwEvent::DeviceRemoved(id));
+    }
+    Ok(true)
+}
+
+/// Watch the settings metadata until `key` has `value` or `timeout` runs out.
+///
+/// Returns whether the setting got there in time, right away if it already was.
+/// A key the metadata does not hold reads as unset, so `clock.force-rate` `0` also
+/// matches when nothing is forced. A key or value [`Settings::has_value`] cannot read
+/// never matches.
+pub fn wait_settle(key: &str, value: &str, timeout: Duration) -> Result<bool, PwError> {
+    let opts = WatchOptions {
+        timeout: Some(timeout),
+        settings_only: true,
+        ..Default::default()
+    };
+    let settled = Rc::new(Cell::new(false));
+    let stop = opts.stop.clone();
+    let (key, value) = (key.to_owned(), value.to_owned());
+    let mut current = Settings::default();
+    let mut synced = false;
+    watch(opts, {
+        let settled = settled.clone();
+        move |event| {
+            match event {
+                PwEvent::SettingsChanged(settings) => current = settings,
+                PwEvent::Synced => synced = true,
+                _ => return,
+            }
+            // Before the first sync the settings may be only partly read.
+            if synced && current.has_value(&key, &value) == Some(true) {
+                settled.set(true);
+                stop.stop();
+            }
+        }
+    })?;
+    Ok(settled.get())
+}
+
+/// Read the clock settings alone, without binding any node, for callers that only
+/// need a number or two and want them fast.
+pub fn read_settings() -> Result<Settings, PwError> {
+    let opts = WatchOptions {
+        settings_only: true,
+        ..Default::default()
+    };
+    let settings = Rc::new(RefCell::new(Settings::default()));
+    let stop = opts.stop.clone();
+    watch(opts, {
+        let settings = settings.clone();
+        move |event| match event {
+            PwEvent::SettingsChanged(current) => *settings.borrow_mut() = current,
+            PwEvent::Synced => stop.stop(),
+            _ => {}
+        }
+    })?;
+    Ok(settings.take())
+}